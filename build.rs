@@ -0,0 +1,39 @@
+// build.rs
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+}
+
+fn main() {
+    // Prefer an explicit GIT_SHA (e.g. from CI or a Docker build arg), then git.
+    let sha = std::env::var("GIT_SHA")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .or_else(|| git(&["rev-parse", "--short=12", "HEAD"]));
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=HUSHNET_GIT_SHA={sha}");
+    }
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+
+    // HEAD only changes when switching branches; a commit moves the branch
+    // ref, which lives either in its own file or in packed-refs. Paths come
+    // from git so worktrees and a custom GIT_DIR work too. Missing files are
+    // skipped, since cargo would otherwise rerun this on every build.
+    let mut watched = vec!["HEAD".to_owned(), "packed-refs".to_owned()];
+    watched.extend(git(&["symbolic-ref", "-q", "HEAD"]));
+    for name in watched {
+        if let Some(path) = git(&["rev-parse", "--git-path", &name]) {
+            if std::path::Path::new(&path).exists() {
+                println!("cargo:rerun-if-changed={path}");
+            }
+        }
+    }
+}
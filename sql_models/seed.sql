@@ -14,7 +14,7 @@ create table if not exists nodes (
   country_name text,
   last_seen_at timestamptz,
   last_latency_ms integer,
-  status text not null default 'unknown', -- online|offline|unknown
+  status text not null default 'unknown', -- online|offline|unknown|cert_mismatch
   uptime_ratio real default 0
 );
 
@@ -25,5 +25,127 @@ create table if not exists challenges (
   expires_at timestamptz not null
 );
 
+-- cached register outcomes keyed by Idempotency-Key
+create table if not exists idempotency_keys (
+  pubkey_b64 text not null,
+  key text not null,
+  status integer not null,
+  content_type text not null,
+  body bytea not null,
+  created_at timestamptz not null default now(),
+  primary key (pubkey_b64, key)
+);
+
 create index if not exists idx_nodes_status on nodes(status);
 ALTER TABLE nodes ADD CONSTRAINT unique_pubkey UNIQUE (pubkey);
+
+-- schema additions (idempotent, safe to re-run on existing databases)
+alter table nodes add column if not exists last_checked_at timestamptz;
+alter table nodes add column if not exists next_check_at timestamptz;
+create index if not exists idx_nodes_next_check_at on nodes(next_check_at);
+alter table nodes add column if not exists email_verified boolean not null default false;
+alter table nodes add column if not exists consecutive_successes integer not null default 0;
+alter table nodes add column if not exists consecutive_failures integer not null default 0;
+
+-- pending contact_email verification links
+create table if not exists email_verifications (
+  token text primary key,
+  host text not null,
+  email text not null,
+  expires_at timestamptz not null
+);
+alter table nodes add column if not exists updated_at timestamptz not null default now();
+alter table nodes add column if not exists max_connections integer;
+alter table nodes add column if not exists weight integer;
+alter table nodes add column if not exists suspended boolean not null default false;
+alter table nodes add column if not exists tags text[] not null default '{}';
+create index if not exists idx_nodes_tags on nodes using gin(tags);
+alter table nodes add column if not exists alg text not null default 'ed25519';
+alter table challenges add column if not exists alg text not null default 'ed25519';
+alter table nodes add column if not exists tls_fingerprint text;
+alter table nodes add column if not exists last_error text; -- dns|connect|timeout|tls|http_status|bad_body
+alter table nodes add column if not exists approved boolean not null default true;
+alter table nodes add column if not exists is_onion boolean not null default false;
+alter table nodes add column if not exists description text;
+alter table nodes add column if not exists latitude double precision;
+alter table nodes add column if not exists longitude double precision;
+alter table challenges add column if not exists pow_difficulty integer not null default 0;
+alter table nodes add column if not exists last_heartbeat_at timestamptz;
+alter table nodes add column if not exists software_version text;
+alter table nodes add column if not exists reported_software_version text;
+alter table nodes add column if not exists geo_overridden boolean not null default false;
+alter table nodes add column if not exists asn bigint;
+alter table nodes add column if not exists org text;
+alter table nodes add column if not exists self_reported_metrics jsonb;
+alter table nodes add column if not exists operator jsonb;
+
+-- bump updated_at whenever a node's advertised record changes; probe
+-- bookkeeping (latency, last_seen_at, scheduling, counters) does not count
+create or replace function nodes_touch_updated_at() returns trigger as $$
+begin
+  new.updated_at := now();
+  return new;
+end
+$$ language plpgsql;
+
+create or replace trigger nodes_updated_at
+  before update on nodes
+  for each row
+  when ((old.name, old.ip, old.api_base_url, old.pubkey, old.protocol_version, old.features,
+         old.contact_email, old.status, old.country_code, old.country_name, old.email_verified,
+         old.max_connections, old.weight, old.suspended, old.tags, old.alg,
+         old.tls_fingerprint, old.approved, old.description, old.latitude, old.longitude,
+         old.software_version, old.asn, old.org, old.operator)
+        is distinct from
+        (new.name, new.ip, new.api_base_url, new.pubkey, new.protocol_version, new.features,
+         new.contact_email, new.status, new.country_code, new.country_name, new.email_verified,
+         new.max_connections, new.weight, new.suspended, new.tags, new.alg,
+         new.tls_fingerprint, new.approved, new.description, new.latitude, new.longitude,
+         new.software_version, new.asn, new.org, new.operator))
+  execute function nodes_touch_updated_at();
+
+-- one row per health probe, for historical uptime (pruned by the gc task)
+create table if not exists health_checks (
+  id bigserial primary key,
+  host text not null references nodes(host) on delete cascade,
+  checked_at timestamptz not null default now(),
+  status text not null, -- online|offline, raw probe outcome
+  latency_ms integer
+);
+create index if not exists idx_health_checks_host_checked_at on health_checks(host, checked_at);
+create index if not exists idx_health_checks_checked_at on health_checks(checked_at);
+
+-- changes of the published nodes.status, whatever made them (probes,
+-- heartbeats); feeds the status feed (pruned by the gc task)
+create table if not exists status_changes (
+  id bigserial primary key,
+  host text not null references nodes(host) on delete cascade,
+  changed_at timestamptz not null default now(),
+  from_status text not null,
+  to_status text not null
+);
+create index if not exists idx_status_changes_changed_at on status_changes(changed_at);
+
+create or replace function nodes_record_status_change() returns trigger as $$
+begin
+  insert into status_changes (host, from_status, to_status) values (new.host, old.status, new.status);
+  return null;
+end
+$$ language plpgsql;
+
+create or replace trigger nodes_status_change
+  after update of status on nodes
+  for each row
+  when (old.status is distinct from new.status)
+  execute function nodes_record_status_change();
+
+-- admin actions on nodes (edits, suspensions, approvals)
+create table if not exists audit_log (
+  id bigserial primary key,
+  created_at timestamptz not null default now(),
+  action text not null, -- edit|suspend|unsuspend|approve
+  host text,
+  details jsonb not null default '{}'
+);
+create index if not exists idx_audit_log_created_at on audit_log(created_at);
+create index if not exists idx_audit_log_host on audit_log(host, created_at);
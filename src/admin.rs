@@ -0,0 +1,469 @@
+// src/admin.rs
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::audit;
+use crate::cache::ListCache;
+use crate::error::{ApiError, FieldError};
+use crate::health::HealthWorker;
+use crate::maintenance::Maintenance;
+use crate::stats::StatusCounts;
+
+/// Bearer-token check for `/api/admin/*`. Admin endpoints are disabled
+/// entirely when `ADMIN_TOKEN` is unset.
+pub struct AdminAuth {
+    token: Option<String>,
+}
+
+impl AdminAuth {
+    pub fn from_env() -> Self {
+        let token = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+        Self { token }
+    }
+
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        let Some(expected) = self.token.as_deref() else {
+            return Err(ApiError::new(StatusCode::NOT_FOUND, "admin api disabled"));
+        };
+        let provided = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+            return Err(ApiError::new(StatusCode::UNAUTHORIZED, "invalid admin token"));
+        }
+        Ok(())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceReq {
+    pub enabled: bool,
+}
+
+pub async fn set_maintenance(
+    State(admin): State<Arc<AdminAuth>>,
+    State(maintenance): State<Arc<Maintenance>>,
+    headers: HeaderMap,
+    Json(req): Json<MaintenanceReq>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    maintenance.set(req.enabled);
+    Ok(Json(json!({ "maintenance": maintenance.is_enabled() })))
+}
+
+/// Clears GeoIP data for every node so the health worker looks it up again.
+pub async fn rebuild_geoip(
+    State(admin): State<Arc<AdminAuth>>,
+    State(db): State<PgPool>,
+    State(list_cache): State<Arc<ListCache>>,
+    State(worker): State<Arc<HealthWorker>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    // Nothing would refill the cleared columns.
+    if !worker.geoip_enabled() {
+        return Err(ApiError::new(StatusCode::CONFLICT, "GeoIP is disabled"));
+    }
+    let res = sqlx::query(
+        "UPDATE nodes SET country_code=NULL, country_name=NULL, latitude=NULL, longitude=NULL, asn=NULL, org=NULL, geo_overridden=false",
+    )
+        .execute(&db)
+        .await
+        .map_err(crate::internal)?;
+    list_cache.invalidate();
+    tracing::info!("GeoIP rebuild requested for {} nodes", res.rows_affected());
+    Ok(Json(json!({ "queued": res.rows_affected() })))
+}
+
+/// Reports the outcome of the health worker's last tick, and of the last one
+/// that did any work.
+pub async fn health_worker_status(
+    State(admin): State<Arc<AdminAuth>>,
+    State(worker): State<Arc<HealthWorker>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    Ok(Json(json!({ "last_tick": worker.last_tick(), "last_active_tick": worker.last_active_tick() })))
+}
+
+/// Probes every node right away and reports what changed. The scheduled loop
+/// keeps its own timing.
+pub async fn health_tick(
+    State(admin): State<Arc<AdminAuth>>,
+    State(worker): State<Arc<HealthWorker>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    tracing::info!("manual health tick requested");
+    // Spawned so a request timeout does not abort the tick halfway through.
+    let report = tokio::spawn(async move { worker.tick(true).await })
+        .await
+        .map_err(crate::internal)?;
+    Ok(Json(json!({ "tick": report })))
+}
+
+/// Hides a node from `/api/nodes` without deleting it. It keeps being probed.
+pub async fn suspend_node(
+    State(admin): State<Arc<AdminAuth>>,
+    State(db): State<PgPool>,
+    State(list_cache): State<Arc<ListCache>>,
+    State(counts): State<Arc<StatusCounts>>,
+    Path(host): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    set_suspended(&db, &list_cache, &counts, &host, true).await
+}
+
+pub async fn unsuspend_node(
+    State(admin): State<Arc<AdminAuth>>,
+    State(db): State<PgPool>,
+    State(list_cache): State<Arc<ListCache>>,
+    State(counts): State<Arc<StatusCounts>>,
+    Path(host): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    set_suspended(&db, &list_cache, &counts, &host, false).await
+}
+
+async fn set_suspended(
+    db: &PgPool,
+    list_cache: &ListCache,
+    counts: &StatusCounts,
+    host: &str,
+    suspended: bool,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let mut tx = db.begin().await.map_err(crate::internal)?;
+    let res = sqlx::query("UPDATE nodes SET suspended=$1 WHERE host=$2")
+        .bind(suspended)
+        .bind(host)
+        .execute(&mut *tx)
+        .await
+        .map_err(crate::internal)?;
+    if res.rows_affected() == 0 {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "node not found"));
+    }
+    let action = if suspended { "suspend" } else { "unsuspend" };
+    audit::record(&mut *tx, action, Some(host), json!({})).await.map_err(crate::internal)?;
+    tx.commit().await.map_err(crate::internal)?;
+    list_cache.invalidate();
+    counts.poke();
+    Ok(Json(json!({ "host": host, "suspended": suspended })))
+}
+
+/// Nodes registered while `APPROVAL_REQUIRED` was on and not yet approved,
+/// oldest first.
+pub async fn pending_nodes(
+    State(admin): State<Arc<AdminAuth>>,
+    State(db): State<PgPool>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM nodes WHERE NOT approved ORDER BY registered_at, host",
+        crate::NODE_COLUMNS
+    ))
+    .fetch_all(&db)
+    .await
+    .map_err(crate::internal)?;
+    let nodes: Vec<serde_json::Value> = rows.iter().map(crate::node_json).collect();
+    Ok(Json(json!({ "nodes": nodes })))
+}
+
+/// Admits a pending node to the public list. Approval is kept across
+/// re-registrations.
+pub async fn approve_node(
+    State(admin): State<Arc<AdminAuth>>,
+    State(db): State<PgPool>,
+    State(list_cache): State<Arc<ListCache>>,
+    State(counts): State<Arc<StatusCounts>>,
+    Path(host): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    let mut tx = db.begin().await.map_err(crate::internal)?;
+    let res = sqlx::query("UPDATE nodes SET approved=true WHERE host=$1")
+        .bind(&host)
+        .execute(&mut *tx)
+        .await
+        .map_err(crate::internal)?;
+    if res.rows_affected() == 0 {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "node not found"));
+    }
+    audit::record(&mut *tx, "approve", Some(&host), json!({})).await.map_err(crate::internal)?;
+    tx.commit().await.map_err(crate::internal)?;
+    list_cache.invalidate();
+    counts.poke();
+    Ok(Json(json!({ "host": host, "approved": true })))
+}
+
+/// Fields `PATCH /api/admin/nodes/:host` may change. Keys and endpoints stay
+/// under the node's own signature.
+const EDITABLE_FIELDS: &[&str] = &["name", "country_code", "country_name", "suspended", "tags"];
+
+/// `None` when `field` is absent, `Some(None)` when it is `null`.
+fn nullable_str(
+    body: &Value,
+    field: &str,
+    valid: impl Fn(&str) -> bool,
+    message: &str,
+    errors: &mut Vec<FieldError>,
+) -> Option<Option<String>> {
+    match body.get(field)? {
+        Value::Null => Some(None),
+        Value::String(s) if valid(s.trim()) => Some(Some(s.trim().to_owned())),
+        _ => {
+            errors.push(FieldError::new(field, message));
+            None
+        }
+    }
+}
+
+/// Corrects a node's record on behalf of its operator. Only the fields
+/// present in the body change; each change is written to the audit log.
+/// A `country_code` set here is kept from then on instead of being
+/// overwritten by GeoIP; setting it to `null` hands the node back to GeoIP.
+pub async fn edit_node(
+    State(admin): State<Arc<AdminAuth>>,
+    State(db): State<PgPool>,
+    State(list_cache): State<Arc<ListCache>>,
+    State(counts): State<Arc<StatusCounts>>,
+    Path(host): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Json<Value>, ApiError> {
+    admin.check(&headers)?;
+    let Some(fields) = body.as_object() else {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "body must be a JSON object"));
+    };
+    let mut errors: Vec<FieldError> = fields
+        .keys()
+        .filter(|k| !EDITABLE_FIELDS.contains(&k.as_str()))
+        .map(|k| FieldError::new(k.as_str(), "cannot be edited"))
+        .collect();
+    let name = match nullable_str(&body, "name", |s| !s.is_empty(), "must be a non-empty string", &mut errors) {
+        Some(None) => {
+            errors.push(FieldError::new("name", "must be a non-empty string"));
+            None
+        }
+        other => other.flatten(),
+    };
+    let country_code = nullable_str(
+        &body,
+        "country_code",
+        |s| s.len() == 2 && s.bytes().all(|b| b.is_ascii_alphabetic()),
+        "must be a two-letter ISO code or null",
+        &mut errors,
+    )
+    .map(|c| c.map(|c| c.to_ascii_uppercase()));
+    let country_name = nullable_str(
+        &body,
+        "country_name",
+        |s| !s.is_empty() && s.chars().count() <= 64,
+        "must be a string of 1 to 64 characters or null",
+        &mut errors,
+    );
+    let suspended = match body.get("suspended") {
+        None => None,
+        Some(Value::Bool(b)) => Some(*b),
+        Some(_) => {
+            errors.push(FieldError::new("suspended", "must be a boolean"));
+            None
+        }
+    };
+    let tags = fields.contains_key("tags").then(|| crate::validate::tags(&body, &mut errors));
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+    // Handing the node back to GeoIP also clears the name that went with the code.
+    let country_name = match (&country_code, country_name) {
+        (Some(None), None) => Some(None),
+        (_, country_name) => country_name,
+    };
+
+    let mut tx = db.begin().await.map_err(crate::internal)?;
+    let row = sqlx::query(
+        "SELECT name, country_code, country_name, suspended, tags FROM nodes WHERE host=$1 FOR UPDATE",
+    )
+    .bind(&host)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(crate::internal)?
+    .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "node not found"))?;
+
+    let mut changes = serde_json::Map::new();
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new("UPDATE nodes SET ");
+    let mut set = qb.separated(", ");
+    let mut changed = |field: &str, from: Value, to: Value| {
+        let differs = from != to;
+        if differs {
+            changes.insert(field.to_owned(), json!({ "from": from, "to": to }));
+        }
+        differs
+    };
+    if let Some(name) = name {
+        if changed("name", json!(row.get::<String, _>("name")), json!(name)) {
+            set.push("name = ").push_bind_unseparated(name);
+        }
+    }
+    let old_code: Option<String> = row.get("country_code");
+    let code_changed = matches!(&country_code, Some(code) if changed("country_code", json!(old_code), json!(code)));
+    let name_changed = matches!(&country_name, Some(cn) if changed("country_name", json!(row.get::<Option<String>, _>("country_name")), json!(cn)));
+    if code_changed {
+        set.push("country_code = ").push_bind_unseparated(country_code.clone().flatten());
+    }
+    if name_changed {
+        set.push("country_name = ").push_bind_unseparated(country_name.flatten());
+    }
+    if code_changed || name_changed {
+        // GeoIP coordinates no longer match a hand-set country.
+        set.push("latitude = NULL");
+        set.push("longitude = NULL");
+        set.push("geo_overridden = ")
+            .push_bind_unseparated(country_code.map_or(old_code, |c| c).is_some());
+    }
+    if let Some(suspended) = suspended {
+        if changed("suspended", json!(row.get::<bool, _>("suspended")), json!(suspended)) {
+            set.push("suspended = ").push_bind_unseparated(suspended);
+        }
+    }
+    if let Some(tags) = tags {
+        if changed("tags", json!(row.get::<Vec<String>, _>("tags")), json!(tags)) {
+            set.push("tags = ").push_bind_unseparated(tags);
+        }
+    }
+
+    if !changes.is_empty() {
+        qb.push(" WHERE host = ").push_bind(&host);
+        qb.build().execute(&mut *tx).await.map_err(crate::internal)?;
+        audit::record(&mut *tx, "edit", Some(&host), json!({ "changes": changes }))
+            .await
+            .map_err(crate::internal)?;
+    }
+    let node = sqlx::query(&format!("SELECT {} FROM nodes WHERE host=$1", crate::NODE_COLUMNS))
+        .bind(&host)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(crate::internal)?;
+    tx.commit().await.map_err(crate::internal)?;
+    if !changes.is_empty() {
+        list_cache.invalidate();
+        counts.poke();
+    }
+    Ok(Json(crate::node_json(&node)))
+}
+
+/// Largest `limit` accepted by the audit log listing.
+const AUDIT_MAX_PAGE_SIZE: i64 = 500;
+
+/// Query string of `GET /api/admin/audit`, checked like the node list's.
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub host: Option<String>,
+    pub action: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Audit log entries, newest first, filtered by host, action and time range
+/// and paged with a keyset cursor on the entry id.
+pub async fn audit_log(
+    State(admin): State<Arc<AdminAuth>>,
+    State(db): State<PgPool>,
+    Query(query): Query<AuditQuery>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    admin.check(&headers)?;
+    let mut errors = Vec::new();
+    let mut time = |field: &str, raw: Option<String>| {
+        raw.and_then(|raw| match DateTime::parse_from_rfc3339(raw.trim()) {
+            Ok(t) => Some(t.with_timezone(&Utc)),
+            Err(_) => {
+                errors.push(FieldError::new(field, "must be an RFC 3339 timestamp"));
+                None
+            }
+        })
+    };
+    let since = time("since", query.since);
+    let until = time("until", query.until);
+    if let Some(action) = query.action.as_deref().filter(|a| !audit::ACTIONS.contains(a)) {
+        errors.push(FieldError::new(
+            "action",
+            format!("unknown action {action}, expected one of {}", audit::ACTIONS.join(",")),
+        ));
+    }
+    let limit = match query.limit.map(|raw| raw.parse::<i64>()) {
+        None => 100,
+        Some(Ok(n)) if (1..=AUDIT_MAX_PAGE_SIZE).contains(&n) => n,
+        Some(_) => {
+            errors.push(FieldError::new("limit", format!("must be between 1 and {AUDIT_MAX_PAGE_SIZE}")));
+            100
+        }
+    };
+    let after = query.after.and_then(|raw| match raw.parse::<i64>() {
+        Ok(id) => Some(id),
+        Err(_) => {
+            errors.push(FieldError::new("after", "is not a valid cursor"));
+            None
+        }
+    });
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    let mut qb: QueryBuilder<Postgres> =
+        QueryBuilder::new("SELECT id, created_at, action, host, details FROM audit_log WHERE true");
+    if let Some(host) = &query.host {
+        qb.push(" AND host = ").push_bind(host);
+    }
+    if let Some(action) = &query.action {
+        qb.push(" AND action = ").push_bind(action);
+    }
+    if let Some(since) = since {
+        qb.push(" AND created_at >= ").push_bind(since);
+    }
+    if let Some(until) = until {
+        qb.push(" AND created_at < ").push_bind(until);
+    }
+    if let Some(after) = after {
+        qb.push(" AND id < ").push_bind(after);
+    }
+    // One extra row tells whether there is a next page.
+    qb.push(" ORDER BY id DESC LIMIT ").push_bind(limit + 1);
+    let mut rows = qb.build().fetch_all(&db).await.map_err(crate::internal)?;
+
+    let next_cursor = (rows.len() as i64 > limit).then(|| {
+        rows.truncate(limit as usize);
+        rows.last().map(|r| r.get::<i64, _>("id").to_string())
+    });
+    let entries: Vec<Value> = rows
+        .iter()
+        .map(|r| {
+            json!({
+                "id": r.get::<i64, _>("id"),
+                "created_at": r.get::<DateTime<Utc>, _>("created_at"),
+                "action": r.get::<String, _>("action"),
+                "host": r.get::<Option<String>, _>("host"),
+                "details": r.get::<Value, _>("details"),
+            })
+        })
+        .collect();
+    Ok(Json(json!({ "entries": entries, "next_cursor": next_cursor.flatten() })))
+}
@@ -0,0 +1,23 @@
+// src/audit.rs
+use serde_json::Value;
+
+/// Every `action` written to `audit_log`.
+pub const ACTIONS: &[&str] = &["edit", "suspend", "unsuspend", "approve"];
+
+/// Appends an entry to `audit_log`. Pass the transaction making the change,
+/// so the entry is written exactly when the change is.
+pub async fn record(
+    db: impl sqlx::PgExecutor<'_>,
+    action: &str,
+    host: Option<&str>,
+    details: Value,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO audit_log (action, host, details) VALUES ($1, $2, $3)")
+        .bind(action)
+        .bind(host)
+        .bind(&details)
+        .execute(db)
+        .await?;
+    tracing::info!("audit: {action} {} {details}", host.unwrap_or("-"));
+    Ok(())
+}
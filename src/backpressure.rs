@@ -0,0 +1,43 @@
+// src/backpressure.rs
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use crate::error::ApiError;
+
+/// Wait suggested to clients turned away because of load.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Cap on write requests in flight at once (`MAX_CONCURRENT_WRITES`,
+/// 0 = unlimited). Requests over the cap are shed immediately instead of
+/// queueing up on the database.
+pub struct WriteLimiter {
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl WriteLimiter {
+    pub fn from_env() -> Self {
+        let max: usize = std::env::var("MAX_CONCURRENT_WRITES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        Self { permits: (max > 0).then(|| Arc::new(Semaphore::new(max))) }
+    }
+}
+
+/// Route layer for write endpoints.
+pub async fn layer(State(limiter): State<Arc<WriteLimiter>>, req: Request, next: Next) -> Response {
+    let Some(permits) = &limiter.permits else {
+        return next.run(req).await;
+    };
+    let Ok(_permit) = permits.clone().try_acquire_owned() else {
+        warn!("shedding write request to {}: too many in flight", req.uri().path());
+        return ApiError::unavailable("too many concurrent writes", RETRY_AFTER_SECS).into_response();
+    };
+    next.run(req).await
+}
@@ -0,0 +1,47 @@
+// src/cache.rs
+use arc_swap::ArcSwapOption;
+use axum::body::Bytes;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    at: Instant,
+    body: Bytes,
+}
+
+/// Short-lived cache of the serialized default `/api/nodes` response.
+pub struct ListCache {
+    ttl: Duration,
+    slot: ArcSwapOption<Entry>,
+}
+
+impl ListCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, slot: ArcSwapOption::empty() }
+    }
+
+    pub fn from_env() -> Self {
+        let ttl_ms: u64 = std::env::var("LIST_CACHE_TTL_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(2000);
+        Self::new(Duration::from_millis(ttl_ms))
+    }
+
+    /// Returns the cached body if it is still within the TTL.
+    pub fn get(&self) -> Option<Bytes> {
+        let entry = self.slot.load_full()?;
+        (entry.at.elapsed() < self.ttl).then(|| entry.body.clone())
+    }
+
+    pub fn put(&self, body: Bytes) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.slot.store(Some(Arc::new(Entry { at: Instant::now(), body })));
+    }
+
+    pub fn invalidate(&self) {
+        self.slot.store(None);
+    }
+}
@@ -1,21 +1,143 @@
 use serde_json::{Map, Value};
+use unicode_normalization::UnicodeNormalization;
 
-pub fn canonical_json_string(value: &Value) -> String {
-    fn stable(v: &Value) -> Value {
+use crate::version;
+
+/// How arrays are treated when canonicalizing.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ArrayOrder {
+    /// Arrays keep their input order, so reordering one changes the
+    /// signature. The default, since order can be meaningful.
+    Preserve,
+    /// Array elements are sorted by their own canonical form, so the order a
+    /// client happens to serialize them in does not matter.
+    Sorted,
+}
+
+/// First protocol version whose register payloads are signed with
+/// [`ArrayOrder::Sorted`]. The version is inside the signed payload, so
+/// signer and registry always agree on the policy.
+pub const SORTED_ARRAYS_SINCE: &str = "1.1";
+
+/// The array policy of a payload, from its `protocol_version`. Older,
+/// missing or unparseable versions preserve order.
+pub fn array_order(payload: &Value) -> ArrayOrder {
+    let version = payload.get("protocol_version").and_then(Value::as_str).and_then(version::parse);
+    let since = version::parse(SORTED_ARRAYS_SINCE).expect("valid version");
+    match version {
+        Some(v) if version::compare(&v, &since).is_ge() => ArrayOrder::Sorted,
+        _ => ArrayOrder::Preserve,
+    }
+}
+
+/// Canonical form signed by nodes: object keys sorted, arrays ordered per
+/// `arrays`, and every string (keys and values) normalized to Unicode NFC.
+pub fn canonical_json_string(value: &Value, arrays: ArrayOrder) -> String {
+    fn stable(v: &Value, arrays: ArrayOrder) -> Value {
         match v {
             Value::Object(m) => {
-                let mut keys: Vec<_> = m.keys().cloned().collect();
-                keys.sort();
+                let mut entries: Vec<(String, &Value)> =
+                    m.iter().map(|(k, v)| (k.nfc().collect(), v)).collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
                 let mut out = Map::new();
-                for k in keys {
-                    out.insert(k.clone(), stable(&m[&k]));
+                for (k, v) in entries {
+                    out.insert(k, stable(v, arrays));
                 }
                 Value::Object(out)
             }
-            Value::Array(arr) => Value::Array(arr.iter().map(stable).collect()),
+            Value::Array(arr) => {
+                let mut items: Vec<Value> = arr.iter().map(|v| stable(v, arrays)).collect();
+                if arrays == ArrayOrder::Sorted {
+                    items.sort_by_cached_key(|v| serde_json::to_string(v).unwrap());
+                }
+                Value::Array(items)
+            }
+            Value::String(s) => Value::String(s.nfc().collect()),
             _ => v.clone(),
         }
     }
-    let s = stable(value);
+    let s = stable(value, arrays);
     serde_json::to_string(&s).unwrap()
-}
\ No newline at end of file
+}
+
+/// Bytes a node signs to register: `canonical_json(payload) || nonce`, with
+/// the array policy of the payload's protocol version.
+pub fn register_message(payload: &Value, nonce: &str) -> Vec<u8> {
+    let canonical = canonical_json_string(payload, array_order(payload));
+    [canonical.as_bytes(), nonce.as_bytes()].concat()
+}
+
+/// Bytes a node signs to heartbeat: `host || nonce`, followed by
+/// `canonical_json(metrics)` when it reports metrics.
+pub fn heartbeat_message(host: &str, nonce: &str, metrics: Option<&Value>) -> Vec<u8> {
+    let mut message = [host.as_bytes(), nonce.as_bytes()].concat();
+    if let Some(metrics) = metrics {
+        message.extend(canonical_json_string(metrics, ArrayOrder::Preserve).as_bytes());
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn precomposed_and_combining_strings_canonicalize_identically() {
+        // "é" as U+00E9 and as "e" + U+0301.
+        let precomposed = json!({ "name": "Caf\u{e9}", "contact_email": "ren\u{e9}@example.com" });
+        let combining = json!({ "name": "Cafe\u{301}", "contact_email": "rene\u{301}@example.com" });
+        assert_ne!(precomposed, combining);
+        assert_eq!(
+            canonical_json_string(&precomposed, ArrayOrder::Preserve),
+            canonical_json_string(&combining, ArrayOrder::Preserve),
+        );
+        assert_eq!(register_message(&precomposed, "n"), register_message(&combining, "n"));
+    }
+
+    #[test]
+    fn keys_and_nested_strings_are_normalized() {
+        let precomposed = json!({ "\u{c5}": ["\u{f1}"], "tags": { "k": "\u{e9}" } });
+        let combining = json!({ "A\u{30a}": ["n\u{303}"], "tags": { "k": "e\u{301}" } });
+        let canonical = canonical_json_string(&combining, ArrayOrder::Preserve);
+        assert_eq!(canonical_json_string(&precomposed, ArrayOrder::Preserve), canonical);
+        assert_eq!(canonical, "{\"tags\":{\"k\":\"\u{e9}\"},\"\u{c5}\":[\"\u{f1}\"]}");
+    }
+
+    #[test]
+    fn protocol_version_selects_the_array_policy() {
+        for (version, order) in [
+            ("1.0", ArrayOrder::Preserve),
+            ("1", ArrayOrder::Preserve),
+            ("garbage", ArrayOrder::Preserve),
+            ("1.1", ArrayOrder::Sorted),
+            ("v1.1.0", ArrayOrder::Sorted),
+            ("2.0", ArrayOrder::Sorted),
+        ] {
+            assert!(array_order(&json!({ "protocol_version": version })) == order, "{version}");
+        }
+        assert!(array_order(&json!({})) == ArrayOrder::Preserve);
+    }
+
+    #[test]
+    fn preserved_arrays_keep_their_order() {
+        let a = json!({ "protocol_version": "1.0", "tags": ["eu", "b", "a"] });
+        let b = json!({ "protocol_version": "1.0", "tags": ["a", "b", "eu"] });
+        assert_eq!(
+            canonical_json_string(&a, array_order(&a)),
+            r#"{"protocol_version":"1.0","tags":["eu","b","a"]}"#
+        );
+        assert_ne!(register_message(&a, "n"), register_message(&b, "n"));
+    }
+
+    #[test]
+    fn sorted_arrays_ignore_input_order_at_any_depth() {
+        let a = json!({ "protocol_version": "1.1", "tags": ["eu", "b", "a"], "features": { "x": [{ "k": 2 }, { "k": 1 }] } });
+        let b = json!({ "protocol_version": "1.1", "tags": ["a", "eu", "b"], "features": { "x": [{ "k": 1 }, { "k": 2 }] } });
+        assert_eq!(
+            canonical_json_string(&a, array_order(&a)),
+            r#"{"features":{"x":[{"k":1},{"k":2}]},"protocol_version":"1.1","tags":["a","b","eu"]}"#
+        );
+        assert_eq!(register_message(&a, "n"), register_message(&b, "n"));
+    }
+}
@@ -0,0 +1,74 @@
+// src/cli.rs
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use clap::{Parser, Subcommand};
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+use crate::{canon, pow};
+
+#[derive(Parser)]
+#[command(version, about = "HushNet registry server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the registry server (default).
+    Serve,
+    /// Print a ready-to-POST register request signed with a node key.
+    Sign {
+        /// Ed25519 secret key: 32 raw bytes or their base64 encoding.
+        #[arg(long)]
+        key: PathBuf,
+        /// JSON file holding the register payload; `pubkey` is filled in.
+        #[arg(long)]
+        payload: PathBuf,
+        /// Nonce obtained from /api/registry/challenge.
+        #[arg(long)]
+        nonce: String,
+        /// `pow_difficulty` from the challenge response, if it had one.
+        #[arg(long, default_value_t = 0)]
+        pow_difficulty: u32,
+    },
+}
+
+pub fn sign(key: &Path, payload: &Path, nonce: &str, pow_difficulty: u32) -> anyhow::Result<()> {
+    let signing_key = read_key(key)?;
+    let mut payload: serde_json::Value = serde_json::from_slice(&std::fs::read(payload)?)?;
+    let pubkey_b64 = B64.encode(signing_key.verifying_key().to_bytes());
+    payload
+        .as_object_mut()
+        .ok_or_else(|| anyhow::anyhow!("payload must be a JSON object"))?
+        .insert("pubkey".into(), json!(pubkey_b64));
+
+    let message = canon::register_message(&payload, nonce);
+    let signature = signing_key.sign(&message);
+
+    let mut req = json!({
+        "payload": payload,
+        "nonce": nonce,
+        "signature_b64": B64.encode(signature.to_bytes()),
+        "pubkey_b64": pubkey_b64,
+    });
+    if pow_difficulty > 0 {
+        req["pow_nonce"] = json!(pow::solve(nonce, pow_difficulty.min(pow::MAX_DIFFICULTY)));
+    }
+    println!("{}", serde_json::to_string_pretty(&req)?);
+    Ok(())
+}
+
+fn read_key(path: &Path) -> anyhow::Result<SigningKey> {
+    let raw = std::fs::read(path)?;
+    let bytes = if raw.len() == 32 {
+        raw
+    } else {
+        B64.decode(String::from_utf8(raw)?.trim())?
+    };
+    let seed: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("key must be 32 bytes"))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
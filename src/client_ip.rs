@@ -0,0 +1,52 @@
+// src/client_ip.rs
+use axum::http::HeaderMap;
+use sqlx::types::ipnetwork::IpNetwork;
+use std::net::{IpAddr, SocketAddr};
+
+/// Which reverse proxies may tell us the real client address.
+pub struct ProxyTrust {
+    /// Proxies in front of the registry; the client is the `count`-th
+    /// `X-Forwarded-For` entry from the right. 0 ignores the header.
+    count: usize,
+    /// Peers allowed to set `X-Forwarded-For` at all.
+    cidrs: Vec<IpNetwork>,
+}
+
+impl ProxyTrust {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let count = std::env::var("TRUSTED_PROXY_COUNT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        let cidrs = std::env::var("TRUSTED_PROXY_CIDRS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().map_err(|_| anyhow::anyhow!("invalid TRUSTED_PROXY_CIDRS entry: {s}")))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { count, cidrs })
+    }
+
+    /// The address of the client behind any trusted proxies. Forwarded
+    /// headers are only believed when the socket peer is a trusted proxy;
+    /// anything unexpected falls back to the peer address.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+        let peer_ip = peer.ip();
+        if self.count == 0 || !self.cidrs.iter().any(|net| net.contains(peer_ip)) {
+            return peer_ip;
+        }
+        let hops: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+        hops.len()
+            .checked_sub(self.count)
+            .and_then(|i| hops[i].parse().ok())
+            .unwrap_or(peer_ip)
+    }
+}
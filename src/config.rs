@@ -0,0 +1,110 @@
+// src/config.rs
+use crate::client_ip::ProxyTrust;
+use crate::version::{self, ProtocolCompat};
+
+/// Settings read once from the environment at startup.
+pub struct Config {
+    pub protocol_compat: ProtocolCompat,
+    /// Raw and parsed form of `MIN_REGISTER_PROTOCOL`.
+    pub min_register_protocol: Option<(String, Vec<u64>)>,
+    /// Renaming a registered node needs `allow_rename: true` in the payload.
+    pub name_change_requires_flag: bool,
+    /// Largest number of entries accepted by the batch heartbeat endpoint.
+    pub heartbeat_batch_max: usize,
+    /// Largest number of entries accepted by the batch challenge endpoint.
+    pub challenge_batch_max: usize,
+    pub proxies: ProxyTrust,
+    /// Payload fields register requires on top of the core ones.
+    pub required_payload_fields: Vec<String>,
+    /// New nodes stay out of the public list until an admin approves them.
+    pub approval_required: bool,
+    /// Decimal places kept in latency aggregates; 0 yields integers.
+    pub latency_decimals: u32,
+    /// `RESERVED_NAME_PATTERNS`, folded with `validate::name_key`.
+    pub reserved_names: Vec<String>,
+    /// Leading zero bits a register `pow_nonce` must produce; 0 disables.
+    pub pow_difficulty: u32,
+    pub feature_limits: crate::validate::FeatureLimits,
+    /// Heartbeats from one host closer together than this get a 429.
+    pub min_heartbeat_interval_secs: u64,
+    /// Lets `api_base_url` point at a different host than `host`.
+    pub allow_mismatched_api_host: bool,
+    /// Cap on registered hosts; 0 means no cap.
+    pub max_total_nodes: i64,
+    /// Register connects to `api_base_url` first, giving up after this.
+    pub reachability_timeout: Option<std::time::Duration>,
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let min_register_protocol = match std::env::var("MIN_REGISTER_PROTOCOL") {
+            Ok(raw) if !raw.trim().is_empty() => {
+                let parsed = version::parse(&raw)
+                    .ok_or_else(|| anyhow::anyhow!("invalid MIN_REGISTER_PROTOCOL: {raw}"))?;
+                Some((raw.trim().to_owned(), parsed))
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            protocol_compat: ProtocolCompat::from_env(),
+            min_register_protocol,
+            name_change_requires_flag: env_flag("NAME_CHANGE_REQUIRES_FLAG", false),
+            heartbeat_batch_max: std::env::var("HEARTBEAT_BATCH_MAX")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(100),
+            challenge_batch_max: std::env::var("CHALLENGE_BATCH_MAX")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(100),
+            proxies: ProxyTrust::from_env()?,
+            required_payload_fields: std::env::var("REQUIRED_PAYLOAD_FIELDS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            approval_required: env_flag("APPROVAL_REQUIRED", false),
+            latency_decimals: std::env::var("LATENCY_DECIMALS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0)
+                .min(3),
+            reserved_names: std::env::var("RESERVED_NAME_PATTERNS")
+                .unwrap_or_default()
+                .split(',')
+                .map(crate::validate::name_key)
+                .filter(|p| !p.is_empty())
+                .collect(),
+            pow_difficulty: std::env::var("POW_DIFFICULTY")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0)
+                .min(crate::pow::MAX_DIFFICULTY),
+            feature_limits: crate::validate::FeatureLimits::from_env(),
+            min_heartbeat_interval_secs: std::env::var("MIN_HEARTBEAT_INTERVAL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(30),
+            allow_mismatched_api_host: env_flag("ALLOW_MISMATCHED_API_HOST", false),
+            max_total_nodes: std::env::var("MAX_TOTAL_NODES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            reachability_timeout: std::env::var("REGISTER_REACHABILITY_TIMEOUT_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map(std::time::Duration::from_millis),
+        })
+    }
+}
+
+pub fn env_flag(name: &str, default: bool) -> bool {
+    match std::env::var(name) {
+        Ok(v) => matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,35 @@
+// src/content_type.rs
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ApiError;
+
+/// Whether the request declares a JSON body: `application/json` or an
+/// `application/*+json` type, parameters such as `charset` ignored.
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(value) = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let mime = value.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    match mime.strip_prefix("application/") {
+        Some(subtype) => subtype == "json" || subtype.ends_with("+json"),
+        None => false,
+    }
+}
+
+/// Route layer for endpoints taking a JSON body: anything not declared as
+/// JSON gets `415` before the body is read.
+pub async fn require_json(req: Request, next: Next) -> Response {
+    if !is_json(req.headers()) {
+        return ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected Content-Type: application/json",
+        )
+        .into_response();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,135 @@
+// src/crypto.rs
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    DecodeError, Engine,
+};
+use axum::http::StatusCode;
+
+use crate::error::ApiError;
+
+const LENIENT: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const STANDARD_LENIENT: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, LENIENT);
+
+/// Decodes standard base64 whether or not it is padded, ignoring surrounding
+/// whitespace. Keys and signatures are always compared as decoded bytes, so
+/// two spellings of the same key are treated as the same key.
+pub fn decode_b64(s: &str) -> Result<Vec<u8>, DecodeError> {
+    STANDARD_LENIENT.decode(s.trim())
+}
+
+/// Short, non-reversible identifier of a key for diagnostics: the first 8 hex
+/// digits of its SHA-256.
+pub fn fingerprint(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(bytes)[..4].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Longest accepted base64 public key. Ed25519 keys encode to 44 characters;
+/// the slack leaves room for whitespace and future key types.
+pub const MAX_PUBKEY_B64_LEN: usize = 64;
+/// Longest accepted base64 signature (88 characters for Ed25519).
+pub const MAX_SIGNATURE_B64_LEN: usize = 128;
+
+/// Like [`decode_b64`], but rejects `field` with a 400 before decoding when
+/// the string is longer than `max`, so oversized inputs cost nothing.
+pub fn decode_b64_bounded(field: &str, s: &str, max: usize) -> Result<Vec<u8>, ApiError> {
+    if s.len() > max {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("{field} is too long (max {max} characters)"),
+        ));
+    }
+    decode_b64(s).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("invalid {field}: {e}")))
+}
+
+/// Algorithm assumed when a request does not name one.
+pub const DEFAULT_ALG: &str = "ed25519";
+
+/// Why a signature was not accepted.
+pub enum SigError {
+    /// The public key is malformed for this scheme.
+    InvalidKey(String),
+    /// The signature is malformed for this scheme.
+    InvalidSignature(String),
+    /// Well-formed, but does not verify.
+    Mismatch,
+}
+
+impl From<SigError> for ApiError {
+    fn from(e: SigError) -> Self {
+        match e {
+            SigError::InvalidKey(e) => ApiError::new(StatusCode::BAD_REQUEST, format!("invalid pubkey: {e}")),
+            SigError::InvalidSignature(e) => {
+                ApiError::new(StatusCode::BAD_REQUEST, format!("invalid signature: {e}"))
+            }
+            SigError::Mismatch => ApiError::new(StatusCode::UNAUTHORIZED, "bad signature"),
+        }
+    }
+}
+
+/// A signature algorithm nodes can sign with. Adding a key type means
+/// implementing this and listing it in [`scheme`].
+pub trait SignatureScheme: Send + Sync {
+    fn verify(&self, pubkey: &[u8], message: &[u8], signature: &[u8]) -> Result<(), SigError>;
+    /// Whether `pubkey` is a well-formed key for this scheme.
+    fn check_key(&self, pubkey: &[u8]) -> Result<(), String>;
+}
+
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    fn verify(&self, pubkey: &[u8], message: &[u8], signature: &[u8]) -> Result<(), SigError> {
+        use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+        let sig = Signature::from_slice(signature).map_err(|e| SigError::InvalidSignature(e.to_string()))?;
+        let vk = VerifyingKey::try_from(pubkey).map_err(|e| SigError::InvalidKey(e.to_string()))?;
+        vk.verify(message, &sig).map_err(|_| SigError::Mismatch)
+    }
+
+    fn check_key(&self, pubkey: &[u8]) -> Result<(), String> {
+        ed25519_dalek::VerifyingKey::try_from(pubkey).map(drop).map_err(|e| e.to_string())
+    }
+}
+
+/// Looks up a scheme by its `alg` name (`None` means [`DEFAULT_ALG`]).
+pub fn scheme(alg: Option<&str>) -> Result<&'static dyn SignatureScheme, ApiError> {
+    match alg.unwrap_or(DEFAULT_ALG) {
+        "ed25519" => Ok(&Ed25519),
+        _ => Err(ApiError::new(StatusCode::BAD_REQUEST, "unsupported alg")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+
+    // Encodes with both `+` and `/` and needs one `=` of padding.
+    const KEY: [u8; 32] = [0xfb; 32];
+
+    #[test]
+    fn padded_and_unpadded_keys_decode_to_the_same_bytes() {
+        let padded = STANDARD.encode(KEY);
+        let unpadded = STANDARD_NO_PAD.encode(KEY);
+        assert!(padded.ends_with('=') && !unpadded.ends_with('='));
+        assert_eq!(decode_b64(&padded).unwrap(), KEY);
+        assert_eq!(decode_b64(&unpadded).unwrap(), KEY);
+        assert_eq!(decode_b64(&format!("  {padded}\n")).unwrap(), KEY);
+    }
+
+    #[test]
+    fn url_safe_alphabet_is_rejected() {
+        let url_safe = URL_SAFE_NO_PAD.encode(KEY);
+        assert!(url_safe.contains('-') && url_safe.contains('_'));
+        assert!(decode_b64(&url_safe).is_err());
+    }
+
+    #[test]
+    fn bounded_decode_rejects_long_input_before_decoding() {
+        let err = decode_b64_bounded("pubkey_b64", &"A".repeat(MAX_PUBKEY_B64_LEN + 1), MAX_PUBKEY_B64_LEN)
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,208 @@
+// src/dns.rs
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::ResolveErrorKind,
+    TokioAsyncResolver,
+};
+use sqlx::{types::ipnetwork::IpNetwork, PgPool, Row};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::cache::ListCache;
+
+pub enum DnsError {
+    Timeout,
+    NotFound(String),
+}
+
+/// Async resolver with a hard upper bound on lookup time.
+pub struct Dns {
+    resolver: TokioAsyncResolver,
+    timeout: Duration,
+}
+
+impl Dns {
+    /// Reads `DNS_TIMEOUT_MS` (default 3000) and `DNS_RESOLVER` (`ip` or
+    /// `ip:port`; system resolver when unset).
+    pub fn from_env() -> anyhow::Result<Self> {
+        let timeout_ms: u64 = std::env::var("DNS_TIMEOUT_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(3000);
+        let timeout = Duration::from_millis(timeout_ms);
+
+        let (config, mut opts) = match std::env::var("DNS_RESOLVER") {
+            Ok(addr) if !addr.trim().is_empty() => {
+                let addr = addr.trim();
+                let sa: SocketAddr = addr
+                    .parse()
+                    .or_else(|_| addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| anyhow::anyhow!("invalid DNS_RESOLVER: {addr}"))?;
+                let group = NameServerConfigGroup::from_ips_clear(&[sa.ip()], sa.port(), true);
+                (ResolverConfig::from_parts(None, vec![], group), ResolverOpts::default())
+            }
+            _ => hickory_resolver::system_conf::read_system_conf().unwrap_or_else(|e| {
+                warn!("could not read system DNS config ({e}), using defaults");
+                (ResolverConfig::default(), ResolverOpts::default())
+            }),
+        };
+        opts.timeout = timeout;
+        opts.attempts = 1;
+
+        Ok(Self { resolver: TokioAsyncResolver::tokio(config, opts), timeout })
+    }
+
+    /// The lowest of the host's addresses (IPv4 before IPv6), so a
+    /// round-robin name maps to the same IP on every registration.
+    pub async fn resolve(&self, host: &str) -> Result<IpAddr, DnsError> {
+        Ok(self.resolve_all(host).await?[0])
+    }
+
+    /// Every address of the host, sorted and deduplicated; never empty.
+    pub async fn resolve_all(&self, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let lookup = tokio::time::timeout(self.timeout, self.resolver.lookup_ip(host))
+            .await
+            .map_err(|_| DnsError::Timeout)?;
+        match lookup {
+            Ok(ips) => {
+                let mut ips: Vec<IpAddr> = ips.iter().collect();
+                ips.sort();
+                ips.dedup();
+                if ips.is_empty() {
+                    return Err(DnsError::NotFound("no records".into()));
+                }
+                Ok(ips)
+            }
+            Err(e) if matches!(e.kind(), ResolveErrorKind::Timeout) => Err(DnsError::Timeout),
+            Err(e) => Err(DnsError::NotFound(e.to_string())),
+        }
+    }
+}
+
+/// Whether `host` is a Tor onion service, which has no IP to resolve.
+pub fn is_onion(host: &str) -> bool {
+    let host = host.as_bytes();
+    host.len() > b".onion".len() && host[host.len() - b".onion".len()..].eq_ignore_ascii_case(b".onion")
+}
+
+/// Whether `ip` is globally routable, i.e. not loopback, private, link-local,
+/// CGNAT, documentation, multicast or unspecified.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                let first = v6.segments()[0];
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80
+                    || first == 0x2001 && v6.segments()[1] == 0x0db8)
+            }
+        },
+    }
+}
+
+/// Re-resolves every registered host every `DNS_REFRESH_INTERVAL_SECS`
+/// (default 3600, 0 disables) and stores the new address when it moved to
+/// another public IP. GeoIP data is cleared so the health worker looks it up
+/// again.
+pub async fn refresh_worker(db: PgPool, dns: Arc<Dns>, list_cache: Arc<ListCache>) {
+    let every_secs: u64 = std::env::var("DNS_REFRESH_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(3600);
+    if every_secs == 0 {
+        return;
+    }
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(every_secs)).await;
+        match refresh_ips(&db, &dns).await {
+            Ok(0) => {}
+            Ok(_) => list_cache.invalidate(),
+            Err(e) => error!("dns refresh error: {e}"),
+        }
+    }
+}
+
+async fn refresh_ips(db: &PgPool, dns: &Dns) -> sqlx::Result<usize> {
+    let nodes = sqlx::query("SELECT host, ip FROM nodes WHERE NOT is_onion").fetch_all(db).await?;
+    let mut changed = 0;
+    for row in nodes {
+        let host: String = row.get("host");
+        let old: Option<IpNetwork> = row.get("ip");
+        let ips = match dns.resolve_all(&host).await {
+            Ok(ips) => ips,
+            Err(DnsError::Timeout) => {
+                warn!("dns refresh: timeout resolving {host}");
+                continue;
+            }
+            Err(DnsError::NotFound(e)) => {
+                warn!("dns refresh: could not resolve {host}: {e}");
+                continue;
+            }
+        };
+        // Still one of the host's addresses: a load-balanced name is not a move.
+        if old.is_some_and(|n| ips.contains(&n.ip())) {
+            continue;
+        }
+        let Some(ip) = ips.iter().copied().find(|ip| is_public(*ip)) else {
+            warn!("dns refresh: {host} now resolves to non-public {ips:?}, keeping the stored address");
+            continue;
+        };
+        // A country set by an admin is kept; GeoIP data is redone for the new IP.
+        sqlx::query(
+            "UPDATE nodes
+             SET ip=$1,
+                 country_code = CASE WHEN geo_overridden THEN country_code END,
+                 country_name = CASE WHEN geo_overridden THEN country_name END,
+                 latitude=NULL, longitude=NULL
+             WHERE host=$2",
+        )
+            .bind(IpNetwork::from(ip))
+            .bind(&host)
+            .execute(db)
+            .await?;
+        info!("dns refresh: {host} moved from {} to {ip}", old.map_or("none".to_owned(), |n| n.ip().to_string()));
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_onion;
+
+    #[test]
+    fn onion_suffix_is_case_insensitive() {
+        assert!(is_onion("abcdef.onion"));
+        assert!(is_onion("abcdef.ONION"));
+        assert!(!is_onion(".onion"));
+        assert!(!is_onion("example.com"));
+    }
+
+    #[test]
+    fn non_ascii_host_does_not_panic() {
+        assert!(!is_onion("aé12345"));
+        assert!(!is_onion("éééé"));
+        assert!(is_onion("é.onion"));
+    }
+}
@@ -0,0 +1,157 @@
+// src/email.rs
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use lettre::{
+    message::Mailbox, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::cache::ListCache;
+use crate::error::ApiError;
+
+/// Contact email verification (`REQUIRE_EMAIL_VERIFICATION`). When enabled,
+/// registering a new or changed `contact_email` mails a one-time link to
+/// `GET /api/registry/verify-email?token=...`.
+pub struct Mailer {
+    pub required: bool,
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Option<Mailbox>,
+    public_base_url: String,
+    token_ttl: Duration,
+}
+
+impl Mailer {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let required = crate::config::env_flag("REQUIRE_EMAIL_VERIFICATION", false);
+        let transport = match std::env::var("SMTP_URL") {
+            Ok(url) if !url.is_empty() => {
+                Some(AsyncSmtpTransport::<Tokio1Executor>::from_url(&url)?.build())
+            }
+            _ => None,
+        };
+        let from = match std::env::var("SMTP_FROM") {
+            Ok(from) if !from.is_empty() => Some(from.parse()?),
+            _ => None,
+        };
+        if required && (transport.is_none() || from.is_none()) {
+            anyhow::bail!("REQUIRE_EMAIL_VERIFICATION needs SMTP_URL and SMTP_FROM");
+        }
+        let public_base_url = std::env::var("PUBLIC_BASE_URL")
+            .unwrap_or_else(|_| "http://localhost:8080".into())
+            .trim_end_matches('/')
+            .to_owned();
+        let ttl_hours: i64 = std::env::var("EMAIL_VERIFICATION_TTL_HOURS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(48);
+
+        Ok(Self { required, transport, from, public_base_url, token_ttl: Duration::hours(ttl_hours) })
+    }
+
+    /// Stores a fresh token for (host, email) and mails the link in the
+    /// background so register does not wait on SMTP.
+    pub async fn start_verification(
+        self: &Arc<Self>,
+        db: &PgPool,
+        host: &str,
+        email: &str,
+    ) -> anyhow::Result<()> {
+        let mut b = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut b);
+        let token = URL_SAFE_NO_PAD.encode(b);
+
+        sqlx::query("DELETE FROM email_verifications WHERE host=$1")
+            .bind(host)
+            .execute(db)
+            .await?;
+        sqlx::query(
+            "INSERT INTO email_verifications (token, host, email, expires_at) VALUES ($1,$2,$3,$4)",
+        )
+        .bind(&token)
+        .bind(host)
+        .bind(email)
+        .bind(Utc::now() + self.token_ttl)
+        .execute(db)
+        .await?;
+
+        let mailer = self.clone();
+        let (host, email) = (host.to_owned(), email.to_owned());
+        tokio::spawn(async move {
+            if let Err(e) = mailer.send(&host, &email, &token).await {
+                error!("verification email to {email} for {host} failed: {e}");
+            }
+        });
+        Ok(())
+    }
+
+    async fn send(&self, host: &str, email: &str, token: &str) -> anyhow::Result<()> {
+        let (Some(transport), Some(from)) = (&self.transport, &self.from) else {
+            warn!("SMTP not configured, verification email for {host} not sent");
+            return Ok(());
+        };
+        let link = format!("{}/api/registry/verify-email?token={token}", self.public_base_url);
+        let msg = Message::builder()
+            .from(from.clone())
+            .to(email.parse()?)
+            .subject(format!("Verify the contact address for HushNet node {host}"))
+            .body(format!(
+                "This address was registered as the contact for HushNet node {host}.\n\n\
+                 Confirm it by opening:\n{link}\n\n\
+                 If you did not expect this, ignore this message.\n"
+            ))?;
+        transport.send(msg).await?;
+        info!("verification email sent for {host}");
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    pub token: String,
+}
+
+pub async fn verify_email(
+    State(db): State<PgPool>,
+    State(list_cache): State<Arc<ListCache>>,
+    Query(q): Query<VerifyQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let row = sqlx::query(
+        "DELETE FROM email_verifications WHERE token=$1 RETURNING host, email, expires_at",
+    )
+    .bind(&q.token)
+    .fetch_optional(&db)
+    .await
+    .map_err(crate::internal)?;
+    let Some(row) = row else {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "unknown or used token"));
+    };
+    if row.get::<chrono::DateTime<Utc>, _>("expires_at") < Utc::now() {
+        return Err(ApiError::new(StatusCode::GONE, "token expired"));
+    }
+
+    // Only verifies the address the token was issued for; a later email
+    // change invalidates it.
+    let res = sqlx::query(
+        "UPDATE nodes SET email_verified=true WHERE host=$1 AND contact_email=$2",
+    )
+    .bind(row.get::<String, _>("host"))
+    .bind(row.get::<String, _>("email"))
+    .execute(&db)
+    .await
+    .map_err(crate::internal)?;
+    if res.rows_affected() == 0 {
+        return Err(ApiError::new(StatusCode::GONE, "contact email changed since token was issued"));
+    }
+    list_cache.invalidate();
+    Ok(Json(json!({ "ok": true })))
+}
@@ -0,0 +1,167 @@
+// src/error.rs
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+
+/// Used when a 429/503 is raised without an explicit wait hint.
+const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+
+/// Machine-readable error code derived from the status, e.g. `bad_request`.
+fn code_for(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .unwrap_or("error")
+        .to_ascii_lowercase()
+        .replace([' ', '-'], "_")
+        .replace('\'', "")
+}
+
+/// Gives bodiless error responses produced outside the handlers (Axum's
+/// `404` for unknown routes and `405 Method Not Allowed`, the timeout layer's
+/// `408`) the standard JSON error body. Headers, including `Allow`, are kept.
+/// No handler ran to log these, so the body also carries the request's
+/// `x-request-id` as `error.request_id`.
+pub async fn json_fallback_errors(req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    let res = next.run(req).await;
+    let status = res.status();
+    if res.headers().contains_key(header::CONTENT_TYPE) {
+        return res;
+    }
+    let err = match status {
+        StatusCode::NOT_FOUND => ApiError::new(status, "no such endpoint"),
+        StatusCode::METHOD_NOT_ALLOWED => {
+            match res.headers().get(header::ALLOW).and_then(|v| v.to_str().ok()) {
+                Some(allow) => ApiError::new(status, format!("method not allowed, use one of: {allow}")),
+                None => ApiError::new(status, "method not allowed"),
+            }
+        }
+        StatusCode::REQUEST_TIMEOUT => ApiError::new(status, "request timed out").code("timeout"),
+        _ => return res,
+    };
+    let mut body = err.body();
+    if let Some(id) = request_id {
+        body["error"]["request_id"] = id.into();
+    }
+    let (mut parts, _) = res.into_parts();
+    let (json_parts, body) = Json(body).into_response().into_parts();
+    parts.headers.extend(json_parts.headers);
+    Response::from_parts(parts, body)
+}
+
+/// One problem with one request field.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { field: field.into(), message: message.into() }
+    }
+}
+
+/// Error returned by every handler.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// Overrides the `code` derived from the status.
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub retry_after: Option<u64>,
+    /// When set, the body is `{"errors": [...]}` instead of `message`.
+    pub errors: Option<Vec<FieldError>>,
+    /// Extra diagnostics rendered as `error.details`.
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: None,
+            message: message.into(),
+            retry_after: None,
+            errors: None,
+            details: None,
+        }
+    }
+
+    /// `400` listing every invalid field at once.
+    pub fn validation(errors: Vec<FieldError>) -> Self {
+        Self {
+            errors: Some(errors),
+            ..Self::new(StatusCode::BAD_REQUEST, "validation failed")
+        }
+    }
+
+    /// `503` carrying a `Retry-After` of `secs`.
+    pub fn unavailable(message: impl Into<String>, secs: u64) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, message).retry_after(secs)
+    }
+
+    /// `429` carrying a `Retry-After` of `secs`.
+    pub fn too_many_requests(message: impl Into<String>, secs: u64) -> Self {
+        Self::new(StatusCode::TOO_MANY_REQUESTS, message).retry_after(secs)
+    }
+
+    pub fn code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+}
+
+impl ApiError {
+    /// The JSON body, without the status or `Retry-After`.
+    fn body(self) -> serde_json::Value {
+        match self.errors {
+            Some(errors) => json!({ "errors": errors }),
+            None => {
+                let code = self.code.map_or_else(|| code_for(self.status), str::to_owned);
+                let mut error = json!({ "code": code, "message": self.message });
+                if let Some(details) = self.details {
+                    error["details"] = details;
+                }
+                json!({ "error": error })
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status;
+        let retry_after = match status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                Some(self.retry_after.unwrap_or(DEFAULT_RETRY_AFTER_SECS))
+            }
+            _ => self.retry_after,
+        };
+        let mut res = (status, Json(self.body())).into_response();
+        if let Some(secs) = retry_after {
+            res.headers_mut().insert(header::RETRY_AFTER, secs.into());
+        }
+        res
+    }
+}
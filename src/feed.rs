@@ -0,0 +1,104 @@
+// src/feed.rs
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use sqlx::Row;
+use std::fmt::Write;
+
+use crate::error::ApiError;
+use crate::ReadPool;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+/// How far back transitions are looked for.
+const LOOKBACK_DAYS: i32 = 30;
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    pub limit: Option<i64>,
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn rfc3339(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Atom feed of recent node status transitions, newest first. A transition
+/// is a change of the published status, as recorded in `status_changes`;
+/// suspended and unapproved nodes are left out like everywhere else public.
+pub async fn status_feed(
+    State(ReadPool(db)): State<ReadPool>,
+    Query(q): Query<FeedQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+
+    let rows = sqlx::query(
+        r#"
+        SELECT c.id, c.host, c.from_status, c.to_status, c.changed_at
+        FROM status_changes c
+        JOIN nodes n ON n.host = c.host
+        WHERE n.approved AND NOT n.suspended
+          AND c.changed_at >= now() - make_interval(days => $2)
+        ORDER BY c.changed_at DESC, c.id DESC
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .bind(LOOKBACK_DAYS)
+    .fetch_all(&db)
+    .await
+    .map_err(crate::internal)?;
+
+    let updated = rows
+        .first()
+        .map(|r| r.get::<DateTime<Utc>, _>("changed_at"))
+        .unwrap_or_else(Utc::now);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str("  <id>urn:hushnet-registry:status-feed</id>\n");
+    xml.push_str("  <title>HushNet node status changes</title>\n");
+    let _ = writeln!(xml, "  <updated>{}</updated>", rfc3339(updated));
+    for r in &rows {
+        let id: i64 = r.get("id");
+        let host = escape(r.get("host"));
+        let prev = escape(r.get("from_status"));
+        let status = escape(r.get("to_status"));
+        let at = rfc3339(r.get("changed_at"));
+        let _ = write!(
+            xml,
+            "  <entry>\n    <id>urn:hushnet-registry:status-change:{id}</id>\n    \
+             <title>{host}: {prev} → {status}</title>\n    \
+             <updated>{at}</updated>\n    \
+             <content type=\"text\">{host} went from {prev} to {status} at {at}</content>\n  \
+             </entry>\n"
+        );
+    }
+    xml.push_str("</feed>\n");
+
+    Ok(([(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")], xml))
+}
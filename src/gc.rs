@@ -0,0 +1,85 @@
+// src/gc.rs
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::cache::ListCache;
+use crate::stats::StatusCounts;
+
+/// Periodically deletes rows that are only kept for a limited time.
+pub async fn gc_worker(db: PgPool, list_cache: Arc<ListCache>, counts: Arc<StatusCounts>) {
+    let var = |name: &str, default: u64| -> u64 {
+        std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+    };
+    let every_secs = var("GC_INTERVAL_SECS", 3600).max(1);
+    let health_retention_days = var("HEALTH_CHECK_RETENTION_DAYS", 90);
+    let prune_offline_days = var("PRUNE_OFFLINE_AFTER_DAYS", 0);
+
+    loop {
+        match prune_health_checks(&db, health_retention_days).await {
+            Ok(0) => {}
+            Ok(n) => info!("gc: removed {n} health checks older than {health_retention_days} days"),
+            Err(e) => error!("gc: health check pruning failed: {e}"),
+        }
+        match prune_status_changes(&db, health_retention_days).await {
+            Ok(0) => {}
+            Ok(n) => info!("gc: removed {n} status changes older than {health_retention_days} days"),
+            Err(e) => error!("gc: status change pruning failed: {e}"),
+        }
+        match prune_offline_nodes(&db, prune_offline_days).await {
+            Ok(hosts) if hosts.is_empty() => {}
+            Ok(hosts) => {
+                for host in &hosts {
+                    info!("gc: removed node {host}, not seen for over {prune_offline_days} days");
+                }
+                list_cache.invalidate();
+                counts.poke();
+            }
+            Err(e) => error!("gc: offline node pruning failed: {e}"),
+        }
+        tokio::time::sleep(Duration::from_secs(every_secs)).await;
+    }
+}
+
+/// Drops probe history past the retention window (0 keeps everything).
+async fn prune_health_checks(db: &PgPool, days: u64) -> sqlx::Result<u64> {
+    if days == 0 {
+        return Ok(0);
+    }
+    let res = sqlx::query("DELETE FROM health_checks WHERE checked_at < now() - make_interval(days => $1)")
+        .bind(days as i32)
+        .execute(db)
+        .await?;
+    Ok(res.rows_affected())
+}
+
+/// Drops status feed history past the same retention window.
+async fn prune_status_changes(db: &PgPool, days: u64) -> sqlx::Result<u64> {
+    if days == 0 {
+        return Ok(0);
+    }
+    let res = sqlx::query("DELETE FROM status_changes WHERE changed_at < now() - make_interval(days => $1)")
+        .bind(days as i32)
+        .execute(db)
+        .await?;
+    Ok(res.rows_affected())
+}
+
+/// Deletes nodes that are not online and have not been seen (or, if never
+/// seen, registered) for `days` days (0 disables). Their probe history goes
+/// with them.
+async fn prune_offline_nodes(db: &PgPool, days: u64) -> sqlx::Result<Vec<String>> {
+    if days == 0 {
+        return Ok(Vec::new());
+    }
+    sqlx::query_scalar(
+        "DELETE FROM nodes
+         WHERE status <> 'online'
+           AND coalesce(last_seen_at, registered_at) < now() - make_interval(days => $1)
+         RETURNING host",
+    )
+    .bind(days as i32)
+    .fetch_all(db)
+    .await
+}
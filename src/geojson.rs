@@ -0,0 +1,43 @@
+// src/geojson.rs
+use axum::{extract::State, http::header, response::IntoResponse};
+use serde_json::json;
+use sqlx::Row;
+
+use crate::error::ApiError;
+use crate::ReadPool;
+
+/// Listed nodes with GeoIP coordinates as a GeoJSON FeatureCollection, for
+/// plotting on a map. Nodes without coordinates are left out.
+pub async fn nodes_geojson(State(ReadPool(db)): State<ReadPool>) -> Result<impl IntoResponse, ApiError> {
+    let rows = sqlx::query(
+        "SELECT name, host, status, country_code, latitude, longitude
+         FROM nodes
+         WHERE NOT suspended AND approved AND latitude IS NOT NULL AND longitude IS NOT NULL
+         ORDER BY host",
+    )
+    .fetch_all(&db)
+    .await
+    .map_err(crate::internal)?;
+
+    let features: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|r| {
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    // GeoJSON positions are [longitude, latitude].
+                    "coordinates": [r.get::<f64, _>("longitude"), r.get::<f64, _>("latitude")],
+                },
+                "properties": {
+                    "name": r.get::<String, _>("name"),
+                    "host": r.get::<String, _>("host"),
+                    "status": r.get::<String, _>("status"),
+                    "country_code": r.get::<Option<String>, _>("country_code"),
+                },
+            })
+        })
+        .collect();
+    let body = json!({ "type": "FeatureCollection", "features": features });
+    Ok(([(header::CONTENT_TYPE, "application/geo+json")], body.to_string()))
+}
@@ -0,0 +1,854 @@
+// src/health.rs
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Utc};
+use maxminddb::geoip2::{Asn, City};
+use maxminddb::{MaxMindDBError, Reader};
+use reqwest::Url;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::cache::ListCache;
+use crate::stats::StatusCounts;
+
+/// Health worker settings, read once at startup.
+struct HealthConfig {
+    timeout_ms: u64,
+    /// Average time between two probes of the same node.
+    interval_secs: u64,
+    /// Each node's next check is `interval ± jitter_pct%`, which spreads
+    /// probes evenly over the interval after a few rounds.
+    jitter_pct: u64,
+    /// How often the worker looks for due nodes.
+    poll_secs: u64,
+    /// Consecutive failed probes needed to flip an online node offline.
+    offline_after: i32,
+    /// Consecutive successful probes needed to flip an offline node online.
+    online_after: i32,
+    /// A new latency replaces the stored one only if it differs by more than
+    /// both `latency_delta_ms` and `latency_delta_pct`% of the stored value.
+    latency_delta_ms: i32,
+    latency_delta_pct: f64,
+    /// A heartbeat younger than this keeps a node online through failed
+    /// probes; 0 lets probes alone decide.
+    stale_after_secs: u64,
+    /// `GEOIP_ENABLED`; when off, neither GeoIP database is opened and the
+    /// geo and ASN columns are left as they are.
+    geoip_enabled: bool,
+}
+
+impl HealthConfig {
+    fn from_env() -> Self {
+        let var = |name: &str, default: u64| -> u64 {
+            std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+        };
+        Self {
+            timeout_ms: var("HEALTH_TIMEOUT_MS", 3000),
+            interval_secs: var("HEALTH_INTERVAL_SECS", 60),
+            jitter_pct: var("HEALTH_JITTER_PCT", 25).min(100),
+            poll_secs: var("HEALTH_POLL_SECS", 5).max(1),
+            offline_after: var("HEALTH_OFFLINE_AFTER", 3).clamp(1, 100) as i32,
+            online_after: var("HEALTH_ONLINE_AFTER", 2).clamp(1, 100) as i32,
+            latency_delta_ms: var("HEALTH_LATENCY_DELTA_MS", 20).min(60_000) as i32,
+            latency_delta_pct: var("HEALTH_LATENCY_DELTA_PCT", 10) as f64 / 100.0,
+            stale_after_secs: var("STALE_AFTER_SECS", 300),
+            geoip_enabled: crate::config::env_flag("GEOIP_ENABLED", true),
+        }
+    }
+}
+
+/// What the health worker did in one tick.
+#[derive(Serialize, Default)]
+pub struct TickReport {
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: u64,
+    pub manual: bool,
+    pub probed: usize,
+    pub went_online: usize,
+    pub went_offline: usize,
+    /// Nodes whose published status changed during the tick.
+    pub changes: Vec<StatusChange>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StatusChange {
+    pub host: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Shared health worker state. The scheduled loop and the admin endpoint run
+/// ticks through it; the probe client lock ensures only one runs at a time.
+pub struct HealthWorker {
+    db: PgPool,
+    cfg: HealthConfig,
+    clients: tokio::sync::Mutex<ProbeClients>,
+    geoip: tokio::sync::Mutex<GeoipBreaker>,
+    geo_dbs: GeoDbs,
+    list_cache: Arc<ListCache>,
+    counts: Arc<StatusCounts>,
+    last_tick: ArcSwapOption<TickReport>,
+    /// Most polls find nothing due, so the last tick that probed a node or
+    /// failed is kept apart from them.
+    last_active_tick: ArcSwapOption<TickReport>,
+}
+
+impl HealthWorker {
+    pub fn new(
+        db: PgPool,
+        client: reqwest::Client,
+        list_cache: Arc<ListCache>,
+        counts: Arc<StatusCounts>,
+    ) -> anyhow::Result<Self> {
+        let by_ip = crate::config::env_flag("PROBE_BY_IP", false);
+        let tor = tor_client()?;
+        let cfg = HealthConfig::from_env();
+        let geo_dbs = GeoDbs { city: GeoDb::new(CITY_DB_PATH), asn: GeoDb::new(ASN_DB_PATH) };
+        if !cfg.geoip_enabled {
+            info!("GeoIP lookups disabled");
+        } else {
+            if let Err(e) = geo_dbs.city.get() {
+                warn!("could not open {CITY_DB_PATH}, will retry when nodes are probed: {e}");
+            }
+            asn_reader(&geo_dbs.asn);
+        }
+        Ok(Self {
+            db,
+            cfg,
+            clients: tokio::sync::Mutex::new(ProbeClients::new(client, by_ip, tor)),
+            geoip: tokio::sync::Mutex::new(GeoipBreaker::from_env()),
+            geo_dbs,
+            list_cache,
+            counts,
+            last_tick: ArcSwapOption::empty(),
+            last_active_tick: ArcSwapOption::empty(),
+        })
+    }
+
+    pub fn geoip_enabled(&self) -> bool {
+        self.cfg.geoip_enabled
+    }
+
+    /// Outcome of the most recent tick, for the admin diagnostics endpoint.
+    pub fn last_tick(&self) -> Option<Arc<TickReport>> {
+        self.last_tick.load_full()
+    }
+
+    /// Outcome of the most recent tick that probed at least one node or
+    /// ended in an error.
+    pub fn last_active_tick(&self) -> Option<Arc<TickReport>> {
+        self.last_active_tick.load_full()
+    }
+
+    /// Runs one tick, waiting for a tick already in progress to finish first.
+    /// A manual tick probes every node instead of only the ones that are due.
+    pub async fn tick(&self, manual: bool) -> Arc<TickReport> {
+        let mut clients = self.clients.lock().await;
+        let mut geoip = self.geoip.lock().await;
+        let start = std::time::Instant::now();
+        let mut report = TickReport { manual, ..Default::default() };
+        let result =
+            tick_health(&self.db, &mut clients, &mut geoip, &self.geo_dbs, &self.cfg, manual, &mut report).await;
+        if let Err(e) = result {
+            error!("health tick error: {e}");
+            report.error = Some(e.to_string());
+        }
+        if report.probed > 0 {
+            self.list_cache.invalidate();
+            self.counts.poke();
+        }
+        report.duration_ms = start.elapsed().as_millis() as u64;
+        report.finished_at = Some(Utc::now());
+        let report = Arc::new(report);
+        self.last_tick.store(Some(report.clone()));
+        if report.probed > 0 || report.error.is_some() {
+            self.last_active_tick.store(Some(report.clone()));
+        }
+        report
+    }
+}
+
+pub async fn health_worker(worker: Arc<HealthWorker>) {
+    loop {
+        worker.tick(false).await;
+        tokio::time::sleep(StdDuration::from_secs(worker.cfg.poll_secs)).await;
+    }
+}
+
+/// Client that reaches onion services through the SOCKS5 proxy in
+/// `TOR_SOCKS_PROXY` (`host:port` or a `socks5h://` URL). Without one, onion
+/// nodes are not probed.
+fn tor_client() -> anyhow::Result<Option<reqwest::Client>> {
+    let raw = match std::env::var("TOR_SOCKS_PROXY") {
+        Ok(raw) if !raw.trim().is_empty() => raw.trim().to_owned(),
+        _ => return Ok(None),
+    };
+    let url = if raw.contains("://") { raw } else { format!("socks5h://{raw}") };
+    let proxy = reqwest::Proxy::all(&url)
+        .map_err(|e| anyhow::anyhow!("invalid TOR_SOCKS_PROXY {url}: {e}"))?;
+    Ok(Some(crate::http_client_builder().proxy(proxy).build()?))
+}
+
+/// Picks the client used to probe a node. With `PROBE_BY_IP` set, each host
+/// gets a client whose DNS for that host is pinned to the stored IP, so the
+/// request still carries the right Host header and SNI but skips resolution.
+/// Pinned clients are kept across ticks to preserve their connection pool.
+struct ProbeClients {
+    shared: reqwest::Client,
+    by_ip: bool,
+    pinned: HashMap<String, (SocketAddr, reqwest::Client)>,
+    tor: Option<reqwest::Client>,
+}
+
+impl ProbeClients {
+    fn new(shared: reqwest::Client, by_ip: bool, tor: Option<reqwest::Client>) -> Self {
+        Self { shared, by_ip, pinned: HashMap::new(), tor }
+    }
+
+    fn for_node(&mut self, api: &str, ip: Option<IpAddr>, onion: bool) -> reqwest::Client {
+        if let (true, Some(tor)) = (onion, &self.tor) {
+            return tor.clone();
+        }
+        let (true, Some(ip)) = (self.by_ip, ip) else {
+            return self.shared.clone();
+        };
+        let Some((domain, port)) = Url::parse(api)
+            .ok()
+            .and_then(|u| Some((u.domain()?.to_owned(), u.port_or_known_default()?)))
+        else {
+            return self.shared.clone();
+        };
+        let addr = SocketAddr::new(ip, port);
+        if let Some((pinned_addr, client)) = self.pinned.get(&domain) {
+            if *pinned_addr == addr {
+                return client.clone();
+            }
+        }
+        match crate::http_client_builder().resolve(&domain, addr).build() {
+            Ok(client) => {
+                self.pinned.insert(domain, (addr, client.clone()));
+                client
+            }
+            Err(e) => {
+                warn!("could not build pinned client for {domain}: {e}");
+                self.shared.clone()
+            }
+        }
+    }
+}
+
+/// Remaining GeoIP lookups allowed in the current tick
+/// (`GEOIP_MAX_LOOKUPS_PER_TICK`, 0 = unlimited).
+struct GeoipBudget(Option<u32>);
+
+impl GeoipBudget {
+    fn take(&mut self) -> bool {
+        match &mut self.0 {
+            None => true,
+            Some(0) => false,
+            Some(n) => {
+                *n -= 1;
+                true
+            }
+        }
+    }
+}
+
+fn geoip_lookups_per_tick() -> GeoipBudget {
+    let max: u32 = std::env::var("GEOIP_MAX_LOOKUPS_PER_TICK")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    GeoipBudget((max > 0).then_some(max))
+}
+
+enum Circuit {
+    Closed,
+    Open { until: Instant },
+    /// Cooldown over; one trial call decides whether to close again.
+    HalfOpen,
+}
+
+/// Circuit breaker around the GeoIP database. After `GEOIP_BREAKER_THRESHOLD`
+/// consecutive failures (0 disables) lookups are skipped for
+/// `GEOIP_BREAKER_COOLDOWN_SECS`, so a broken provider does not slow down or
+/// abort the health sweep. Addresses missing from the database are answers,
+/// not failures.
+struct GeoipBreaker {
+    threshold: u32,
+    cooldown: StdDuration,
+    failures: u32,
+    state: Circuit,
+}
+
+impl GeoipBreaker {
+    fn from_env() -> Self {
+        let var = |name: &str, default: u64| -> u64 {
+            std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+        };
+        Self {
+            threshold: var("GEOIP_BREAKER_THRESHOLD", 5) as u32,
+            cooldown: StdDuration::from_secs(var("GEOIP_BREAKER_COOLDOWN_SECS", 300)),
+            failures: 0,
+            state: Circuit::Closed,
+        }
+    }
+
+    /// Whether a GeoIP call may be made now. Once the cooldown is over this
+    /// lets exactly one trial call through.
+    fn allow(&mut self) -> bool {
+        match self.state {
+            Circuit::Closed => true,
+            Circuit::Open { until } if Instant::now() >= until => {
+                info!("GeoIP circuit half-open, trying one lookup");
+                self.state = Circuit::HalfOpen;
+                true
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen => false,
+        }
+    }
+
+    fn success(&mut self) {
+        if matches!(self.state, Circuit::HalfOpen) {
+            info!("GeoIP circuit closed, lookups resumed");
+        }
+        self.failures = 0;
+        self.state = Circuit::Closed;
+    }
+
+    fn failure(&mut self, err: &dyn std::fmt::Display) {
+        self.failures += 1;
+        let trip = match self.state {
+            Circuit::HalfOpen => true,
+            _ => self.threshold > 0 && self.failures >= self.threshold,
+        };
+        if trip {
+            warn!(
+                "GeoIP circuit open after {} consecutive failures (last: {err}), skipping lookups for {}s",
+                self.failures,
+                self.cooldown.as_secs()
+            );
+            self.state = Circuit::Open { until: Instant::now() + self.cooldown };
+        } else {
+            warn!("GeoIP failure {}: {err}", self.failures);
+        }
+    }
+}
+
+static CITY_DB_PATH: &str = "data/GeoLite2-City.mmdb";
+static ASN_DB_PATH: &str = "data/GeoLite2-ASN.mmdb";
+
+/// A GeoLite2 database file, opened once and then kept for the life of the
+/// process rather than re-read on every tick. Replacing the file takes a
+/// restart.
+struct GeoDb {
+    path: &'static str,
+    reader: ArcSwapOption<Reader<Vec<u8>>>,
+}
+
+impl GeoDb {
+    fn new(path: &'static str) -> Self {
+        Self { path, reader: ArcSwapOption::empty() }
+    }
+
+    /// The open database, opening the file first if that has not worked yet.
+    fn get(&self) -> Result<Arc<Reader<Vec<u8>>>, MaxMindDBError> {
+        if let Some(reader) = self.reader.load_full() {
+            return Ok(reader);
+        }
+        let reader = Arc::new(Reader::open_readfile(self.path)?);
+        info!("opened {}", self.path);
+        self.reader.store(Some(reader.clone()));
+        Ok(reader)
+    }
+}
+
+/// The GeoLite2 databases read by the health worker.
+struct GeoDbs {
+    city: GeoDb,
+    asn: GeoDb,
+}
+
+/// Optional GeoLite2 ASN database, looked up on every probe regardless of
+/// the country lookup. A missing file just means no ASN data; only its
+/// existence is checked again on later ticks.
+fn asn_reader(asn_db: &GeoDb) -> Option<Arc<Reader<Vec<u8>>>> {
+    if let Some(reader) = asn_db.reader.load_full() {
+        return Some(reader);
+    }
+    if !std::path::Path::new(asn_db.path).exists() {
+        return None;
+    }
+    asn_db.get().map_err(|e| warn!("could not open {}: {e}", asn_db.path)).ok()
+}
+
+/// Autonomous system number and organization of `ip`, when known.
+fn asn_lookup(reader: &Reader<Vec<u8>>, ip: IpAddr) -> (Option<i64>, Option<String>) {
+    match reader.lookup::<Asn>(ip) {
+        Ok(asn) => (
+            asn.autonomous_system_number.map(i64::from),
+            asn.autonomous_system_organization.map(str::to_owned),
+        ),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => (None, None),
+        Err(e) => {
+            warn!("ASN lookup for {ip} failed: {e}");
+            (None, None)
+        }
+    }
+}
+
+/// Opens (and drops) a TCP connection to the host and port of
+/// `api_base_url` within `timeout`, for register's optional reachability
+/// check. Addresses that are not publicly routable are refused without
+/// dialing, so the check cannot be pointed at the registry's own network.
+/// The error is a generic reason safe to return to the client; the cause is
+/// only logged.
+pub async fn api_reachable(
+    dns: &crate::dns::Dns,
+    api: &str,
+    timeout: StdDuration,
+) -> Result<(), &'static str> {
+    let url = Url::parse(api).map_err(|_| "invalid url")?;
+    let host = url.host_str().ok_or("invalid url")?.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().ok_or("invalid url")?;
+    let ip = dns.resolve(host).await.map_err(|e| {
+        match e {
+            crate::dns::DnsError::Timeout => debug!("reachability: dns timeout for {host}"),
+            crate::dns::DnsError::NotFound(e) => debug!("reachability: could not resolve {host}: {e}"),
+        }
+        "could not resolve host"
+    })?;
+    if !crate::dns::is_public(ip) {
+        debug!("reachability: {host} resolves to non-public {ip}");
+        return Err("not a public address");
+    }
+    let addr = SocketAddr::new(ip, port);
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => {
+            debug!("reachability: connect to {addr} failed: {e}");
+            Err("unreachable")
+        }
+        Err(_) => {
+            debug!("reachability: no answer from {addr} within {} ms", timeout.as_millis());
+            Err("unreachable")
+        }
+    }
+}
+
+/// What the status decision needs to know about a node besides the probe.
+struct Liveness {
+    status: String,
+    consecutive_successes: i32,
+    consecutive_failures: i32,
+    /// Seconds since the last accepted heartbeat, `None` if there never was one.
+    heartbeat_age_secs: Option<f64>,
+}
+
+/// The status a node gets after a probe whose own outcome was `probe`
+/// (`online`, `offline` or `cert_mismatch`). The rules are the ones listed
+/// under "Node Status" in the README.
+fn next_status<'a>(probe: &'a str, node: &'a Liveness, cfg: &HealthConfig) -> &'a str {
+    // A fresh heartbeat is proof of life on its own.
+    let heartbeat_fresh = cfg.stale_after_secs > 0
+        && node.heartbeat_age_secs.is_some_and(|age| age < cfg.stale_after_secs as f64);
+    match probe {
+        "cert_mismatch" => probe,
+        "offline" if heartbeat_fresh => "online",
+        _ if node.status == "unknown" => probe,
+        "online" if node.consecutive_successes + 1 >= cfg.online_after => probe,
+        "offline" if node.consecutive_failures + 1 >= cfg.offline_after => probe,
+        _ => &node.status,
+    }
+}
+
+/// Software version a node states in its `/health` body, as
+/// `{"version": "..."}` or `{"software_version": "..."}`. Other bodies are
+/// fine and report nothing.
+fn health_body_version(body: &[u8]) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let version = json.get("software_version").or_else(|| json.get("version"))?.as_str()?.trim();
+    (!version.is_empty() && version.chars().count() <= 64 && !version.chars().any(char::is_control))
+        .then(|| version.to_owned())
+}
+
+async fn tick_health(
+    db: &PgPool,
+    clients: &mut ProbeClients,
+    geoip: &mut GeoipBreaker,
+    geo_dbs: &GeoDbs,
+    cfg: &HealthConfig,
+    all: bool,
+    report: &mut TickReport,
+) -> anyhow::Result<()> {
+    // Claim the nodes that are due and push their next check out by a
+    // jittered interval. SKIP LOCKED lets several registry replicas split the
+    // work instead of all probing every node; the claim is committed before
+    // probing so it holds until the next check is due.
+    let mut nodes = sqlx::query(
+        r#"
+        UPDATE nodes
+        SET last_checked_at = now(),
+            next_check_at = now() + make_interval(secs => $1 * (1 + $2 * (2 * random() - 1)))
+        WHERE host IN (
+            SELECT host FROM nodes
+            WHERE (next_check_at IS NULL OR next_check_at <= now() OR $3)
+              AND (NOT is_onion OR $4)
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING host, api_base_url, ip::text AS ip, status, tls_fingerprint, is_onion,
+                  geo_overridden, (country_code IS NULL) AS geo_missing
+        "#,
+    )
+    .bind(cfg.interval_secs as f64)
+    .bind(cfg.jitter_pct as f64 / 100.0)
+    .bind(all)
+    .bind(clients.tor.is_some())
+    .fetch_all(db)
+    .await?;
+    report.probed = nodes.len();
+    if nodes.is_empty() {
+        return Ok(());
+    }
+    let reader = match cfg.geoip_enabled && geoip.allow() {
+        false => None,
+        true => match geo_dbs.city.get() {
+            Ok(reader) => {
+                geoip.success();
+                Some(reader)
+            }
+            Err(e) => {
+                geoip.failure(&e);
+                None
+            }
+        },
+    };
+    let asn_reader = if cfg.geoip_enabled { asn_reader(&geo_dbs.asn) } else { None };
+    // Nodes missing GeoIP data first, so a capped tick refills them before
+    // refreshing the rest.
+    nodes.sort_by_key(|r| !r.get::<bool, _>("geo_missing"));
+    let mut geoip_budget = geoip_lookups_per_tick();
+
+    for row in nodes {
+        let host: String = row.get("host");
+        let api: String = row.get("api_base_url");
+        let ip: Option<String> = row.get("ip");
+        let old_status: String = row.get("status");
+        let pinned: Option<String> = row.get("tls_fingerprint");
+        debug!(%host, %api, "checking health");
+        let stored_ip = ip
+            .as_deref()
+            .and_then(|s| s.split('/').next())
+            .and_then(|s| s.parse::<IpAddr>().ok());
+        let client = clients.for_node(&api, stored_ip, row.get("is_onion"));
+        // Measure latency
+        let start = std::time::Instant::now();
+        let res = client
+            .get(format!("{api}/health"))
+            .timeout(StdDuration::from_millis(cfg.timeout_ms))
+            .send()
+            .await;
+
+        let mut reported_version = None;
+        let (status, latency, probe_error) = match res {
+            Err(e) => ("offline", None, Some(ProbeError::classify(&e))),
+            Ok(r) if pinned.is_some() && cert_fingerprint(&r) != pinned => {
+                warn!("node {host}: TLS certificate does not match the pinned fingerprint");
+                ("cert_mismatch", None, Some(ProbeError::Tls))
+            }
+            Ok(r) if !r.status().is_success() => ("offline", None, Some(ProbeError::HttpStatus)),
+            Ok(r) => {
+                let latency = start.elapsed().as_millis() as i32;
+                match r.bytes().await {
+                    Ok(body) => {
+                        reported_version = health_body_version(&body);
+                        ("online", Some(latency), None)
+                    }
+                    Err(e) if e.is_timeout() => ("offline", None, Some(ProbeError::Timeout)),
+                    Err(_) => ("offline", None, Some(ProbeError::BadBody)),
+                }
+            }
+        };
+
+        // GeoIP if online and geo info missing
+        let (cc, cn, lat, lon) = {
+            let mut country_code: Option<String> = None;
+            let mut country_name: Option<String> = None;
+            let mut latitude: Option<f64> = None;
+            let mut longitude: Option<f64> = None;
+
+            // A country set by an admin is not looked up again.
+            let lookup = reader
+                .as_ref()
+                .zip(ip.as_deref())
+                .filter(|_| !row.get::<bool, _>("geo_overridden") && geoip_budget.take() && geoip.allow());
+            if let Some((reader, mut ip_str)) = lookup {
+                // Remove the /32 or /128 suffix if present
+                if let Some(pos) = ip_str.find('/') {
+                    ip_str = &ip_str[..pos];
+                }
+                debug!(%host, ip = %ip_str, "looking up GeoIP");
+                let ip_addr: std::net::IpAddr = match ip_str.parse() {
+                    Ok(addr) => addr,
+                    Err(_) => {
+                        warn!(%host, ip = %ip_str, "could not parse stored IP for GeoIP lookup");
+                        // skip GeoIP lookup for this node and continue with next node
+                        continue;
+                    }
+                };
+
+                let city = match reader.lookup::<City>(ip_addr) {
+                    Ok(city) => {
+                        geoip.success();
+                        Some(city)
+                    }
+                    Err(MaxMindDBError::AddressNotFoundError(_)) => {
+                        geoip.success();
+                        None
+                    }
+                    Err(e) => {
+                        geoip.failure(&e);
+                        None
+                    }
+                };
+                if let Some(city) = city {
+                    country_code = city.country
+                        .as_ref()
+                        .and_then(|c| c.iso_code)
+                        .map(|s| s.to_string());
+                    country_name = city.country
+                        .as_ref()
+                        .and_then(|c| c.names.as_ref())
+                        .and_then(|m| m.get("en"))
+                        .map(|s| s.to_string());
+                    // Only ever stored as a pair.
+                    if let Some((lat, lon)) =
+                        city.location.as_ref().and_then(|l| Some((l.latitude?, l.longitude?)))
+                    {
+                        latitude = Some(lat);
+                        longitude = Some(lon);
+                    }
+                }
+            }
+
+            (country_code, country_name, latitude, longitude)
+        };
+        let (asn, org) = asn_reader
+            .as_ref()
+            .zip(stored_ip)
+            .map_or((None, None), |(reader, ip)| asn_lookup(reader, ip));
+
+        // The row stays locked from reading the liveness state to writing
+        // the new status, so a heartbeat cannot land in between.
+        let mut tx = db.begin().await?;
+        let Some(current) = sqlx::query(
+            r#"
+            SELECT status, consecutive_successes, consecutive_failures,
+                   EXTRACT(EPOCH FROM now() - last_heartbeat_at)::float8 AS heartbeat_age_secs
+            FROM nodes WHERE host = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(&host)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            // Deleted since it was claimed.
+            continue;
+        };
+        let liveness = Liveness {
+            status: current.get("status"),
+            consecutive_successes: current.get("consecutive_successes"),
+            consecutive_failures: current.get("consecutive_failures"),
+            heartbeat_age_secs: current.get("heartbeat_age_secs"),
+        };
+        let new_status = next_status(status, &liveness, cfg).to_owned();
+        sqlx::query(
+            r#"
+            UPDATE nodes
+            SET status = $6,
+                consecutive_successes = CASE WHEN $1 = 'online' THEN consecutive_successes + 1 ELSE 0 END,
+                consecutive_failures = CASE WHEN $1 = 'offline' THEN consecutive_failures + 1 ELSE 0 END,
+                last_latency_ms = CASE
+                    WHEN $2 IS NULL OR last_latency_ms IS NULL
+                      OR abs($2 - last_latency_ms) > greatest($7, last_latency_ms * $8)
+                    THEN $2
+                    ELSE last_latency_ms
+                END,
+                last_seen_at = CASE WHEN $1='online' THEN now() ELSE last_seen_at END,
+                country_code = COALESCE($3, country_code),
+                country_name = COALESCE($4, country_name),
+                latitude = COALESCE($10, latitude),
+                longitude = COALESCE($11, longitude),
+                asn = COALESCE($13, asn),
+                org = COALESCE($14, org),
+                last_error = $9,
+                reported_software_version = CASE WHEN $1 = 'online' THEN $12 ELSE reported_software_version END
+            WHERE host=$5
+            "#,
+        )
+        .bind(status)
+        .bind(latency)
+        .bind(cc)
+        .bind(cn)
+        .bind(&host)
+        .bind(&new_status)
+        .bind(cfg.latency_delta_ms)
+        .bind(cfg.latency_delta_pct)
+        .bind(probe_error.map(ProbeError::as_str))
+        .bind(lat)
+        .bind(lon)
+        .bind(&reported_version)
+        .bind(asn)
+        .bind(&org)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("INSERT INTO health_checks (host, status, latency_ms) VALUES ($1, $2, $3)")
+            .bind(&host)
+            .bind(status)
+            .bind(latency)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        if new_status != old_status {
+            match new_status.as_str() {
+                "online" => report.went_online += 1,
+                "offline" => report.went_offline += 1,
+                _ => {}
+            }
+            report.changes.push(StatusChange { host, from: old_status, to: new_status });
+        }
+    }
+    Ok(())
+}
+
+/// Why a probe failed, as shown to operators in `last_error`.
+#[derive(Clone, Copy)]
+enum ProbeError {
+    Dns,
+    Connect,
+    Timeout,
+    Tls,
+    HttpStatus,
+    BadBody,
+}
+
+impl ProbeError {
+    fn as_str(self) -> &'static str {
+        match self {
+            ProbeError::Dns => "dns",
+            ProbeError::Connect => "connect",
+            ProbeError::Timeout => "timeout",
+            ProbeError::Tls => "tls",
+            ProbeError::HttpStatus => "http_status",
+            ProbeError::BadBody => "bad_body",
+        }
+    }
+
+    /// reqwest does not expose DNS and TLS failures as kinds, so those are
+    /// recognised from the messages in the error's source chain.
+    fn classify(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return ProbeError::Timeout;
+        }
+        let mut chain = String::new();
+        let mut source: Option<&dyn std::error::Error> = Some(e);
+        while let Some(err) = source {
+            chain.push_str(&err.to_string().to_ascii_lowercase());
+            chain.push('\n');
+            source = err.source();
+        }
+        if chain.contains("dns error") || chain.contains("failed to lookup address") {
+            ProbeError::Dns
+        } else if chain.contains("certificate") || chain.contains("tls") || chain.contains("handshake") {
+            ProbeError::Tls
+        } else if e.is_connect() {
+            ProbeError::Connect
+        } else {
+            ProbeError::BadBody
+        }
+    }
+}
+
+/// Lowercase hex SHA-256 of the leaf certificate the node presented, if the
+/// connection used TLS.
+fn cert_fingerprint(res: &reqwest::Response) -> Option<String> {
+    let der = res.extensions().get::<reqwest::tls::TlsInfo>()?.peer_certificate()?;
+    Some(Sha256::digest(der).iter().map(|b| format!("{b:02x}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> HealthConfig {
+        HealthConfig {
+            timeout_ms: 3000,
+            interval_secs: 60,
+            jitter_pct: 25,
+            poll_secs: 5,
+            offline_after: 3,
+            online_after: 2,
+            latency_delta_ms: 20,
+            latency_delta_pct: 0.1,
+            stale_after_secs: 300,
+            geoip_enabled: true,
+        }
+    }
+
+    fn node(status: &str, failures: i32, heartbeat_age_secs: Option<f64>) -> Liveness {
+        Liveness {
+            status: status.to_owned(),
+            consecutive_successes: 0,
+            consecutive_failures: failures,
+            heartbeat_age_secs,
+        }
+    }
+
+    #[test]
+    fn recent_heartbeat_keeps_node_online_through_failed_probes() {
+        let cfg = cfg();
+        assert_eq!(next_status("offline", &node("online", 10, Some(30.0)), &cfg), "online");
+        assert_eq!(next_status("offline", &node("offline", 10, Some(30.0)), &cfg), "online");
+        assert_eq!(next_status("offline", &node("unknown", 0, Some(30.0)), &cfg), "online");
+    }
+
+    #[test]
+    fn stale_heartbeat_lets_failed_probes_decide() {
+        let cfg = cfg();
+        // Below HEALTH_OFFLINE_AFTER the node keeps its status.
+        assert_eq!(next_status("offline", &node("online", 0, Some(301.0)), &cfg), "online");
+        assert_eq!(next_status("offline", &node("online", 2, Some(301.0)), &cfg), "offline");
+    }
+
+    #[test]
+    fn without_heartbeat_probes_alone_decide() {
+        let cfg = cfg();
+        assert_eq!(next_status("offline", &node("unknown", 0, None), &cfg), "offline");
+        assert_eq!(next_status("offline", &node("online", 1, None), &cfg), "online");
+        assert_eq!(next_status("offline", &node("online", 2, None), &cfg), "offline");
+        assert_eq!(next_status("online", &node("offline", 0, None), &cfg), "offline");
+        let mut recovering = node("offline", 0, None);
+        recovering.consecutive_successes = 1;
+        assert_eq!(next_status("online", &recovering, &cfg), "online");
+    }
+
+    #[test]
+    fn zero_stale_after_ignores_heartbeats() {
+        let cfg = HealthConfig { stale_after_secs: 0, ..cfg() };
+        assert_eq!(next_status("offline", &node("online", 2, Some(1.0)), &cfg), "offline");
+    }
+
+    #[test]
+    fn cert_mismatch_wins_over_a_fresh_heartbeat() {
+        let cfg = cfg();
+        assert_eq!(next_status("cert_mismatch", &node("online", 0, Some(1.0)), &cfg), "cert_mismatch");
+    }
+}
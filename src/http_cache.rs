@@ -0,0 +1,76 @@
+// src/http_cache.rs
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+/// `Cache-Control` for successful public reads (`LIST_CACHE_CONTROL_SECS`,
+/// default 5; 0 makes them `no-store` like everything else).
+pub struct ReadCaching {
+    value: Option<HeaderValue>,
+}
+
+impl ReadCaching {
+    pub fn from_env() -> Self {
+        let secs: u64 = std::env::var("LIST_CACHE_CONTROL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(5);
+        let value = (secs > 0)
+            .then(|| HeaderValue::from_str(&format!("public, max-age={secs}")).expect("valid header"));
+        Self { value }
+    }
+}
+
+/// Success and `304 Not Modified`, the responses a cache may keep.
+fn cacheable(status: StatusCode) -> bool {
+    status.is_success() || status == StatusCode::NOT_MODIFIED
+}
+
+/// Weak entity tag built from the timestamps that move whenever a record's
+/// representation does.
+pub fn etag(stamps: &[Option<DateTime<Utc>>]) -> HeaderValue {
+    let parts: Vec<String> = stamps
+        .iter()
+        .map(|t| t.map_or_else(|| "0".to_owned(), |t| format!("{:x}", t.timestamp_micros())))
+        .collect();
+    HeaderValue::from_str(&format!("W/\"{}\"", parts.join("-"))).expect("valid header")
+}
+
+/// Whether the request's `If-None-Match` lists `etag` (weak comparison) or `*`.
+pub fn not_modified(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    fn opaque(tag: &str) -> &str {
+        tag.trim().trim_start_matches("W/")
+    }
+    let ours = opaque(etag.to_str().unwrap_or_default());
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim() == "*" || opaque(t) == ours)
+}
+
+/// Route layer for public read endpoints, so browsers and CDNs can absorb
+/// repeated reads.
+pub async fn public(State(caching): State<Arc<ReadCaching>>, mut res: Response) -> Response {
+    if let Some(value) = &caching.value {
+        if cacheable(res.status()) {
+            res.headers_mut().insert(header::CACHE_CONTROL, value.clone());
+        }
+    }
+    res
+}
+
+/// Outer layer: error responses are never cached, and any response without
+/// an explicit policy (writes, admin, auth links) is `no-store`.
+pub async fn no_store_by_default(mut res: Response) -> Response {
+    if !cacheable(res.status()) || !res.headers().contains_key(header::CACHE_CONTROL) {
+        res.headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+    res
+}
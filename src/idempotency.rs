@@ -0,0 +1,116 @@
+// src/idempotency.rs
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{PgPool, Row};
+use tracing::error;
+
+use crate::error::ApiError;
+
+const MAX_KEY_LEN: usize = 255;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Route layer honoring an optional `Idempotency-Key` header. The first
+/// non-5xx outcome for a (pubkey, key) pair is stored and replayed verbatim
+/// for retries within `IDEMPOTENCY_TTL_SECS`.
+pub async fn layer(State(db): State<PgPool>, req: Request, next: Next) -> Response {
+    let Some(key) = req
+        .headers()
+        .get("idempotency-key")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+    else {
+        return next.run(req).await;
+    };
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return ApiError::new(StatusCode::BAD_REQUEST, "invalid Idempotency-Key").into_response();
+    }
+
+    let (parts, body) = req.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "body too large").into_response();
+    };
+    let pubkey = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|v| v.get("pubkey_b64").and_then(|p| p.as_str()).map(str::to_owned))
+        .unwrap_or_default();
+
+    let ttl_secs: i64 = std::env::var("IDEMPOTENCY_TTL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(600);
+
+    match sqlx::query(
+        "SELECT status, content_type, body FROM idempotency_keys
+         WHERE pubkey_b64=$1 AND key=$2 AND created_at > now() - make_interval(secs => $3)",
+    )
+    .bind(&pubkey)
+    .bind(&key)
+    .bind(ttl_secs as f64)
+    .fetch_optional(&db)
+    .await
+    {
+        Ok(Some(row)) => return stored_response(&row),
+        Ok(None) => {}
+        Err(e) => error!("idempotency lookup failed: {e}"),
+    }
+
+    let res = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    let status = res.status();
+    if status.is_server_error() {
+        return res;
+    }
+
+    let (parts, body) = res.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal").into_response();
+    };
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("text/plain; charset=utf-8");
+
+    sqlx::query("DELETE FROM idempotency_keys WHERE created_at < now() - make_interval(secs => $1)")
+        .bind(ttl_secs as f64)
+        .execute(&db)
+        .await
+        .ok();
+    if let Err(e) = sqlx::query(
+        "INSERT INTO idempotency_keys (pubkey_b64, key, status, content_type, body)
+         VALUES ($1,$2,$3,$4,$5)
+         ON CONFLICT (pubkey_b64, key) DO UPDATE
+           SET status=EXCLUDED.status, content_type=EXCLUDED.content_type,
+               body=EXCLUDED.body, created_at=now()",
+    )
+    .bind(&pubkey)
+    .bind(&key)
+    .bind(status.as_u16() as i32)
+    .bind(content_type)
+    .bind(bytes.to_vec())
+    .execute(&db)
+    .await
+    {
+        error!("idempotency store failed: {e}");
+    }
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+fn stored_response(row: &sqlx::postgres::PgRow) -> Response {
+    let status = StatusCode::from_u16(row.get::<i32, _>("status") as u16)
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let content_type: String = row.get("content_type");
+    let body: Vec<u8> = row.get("body");
+
+    let mut res = (status, body).into_response();
+    if let Ok(v) = HeaderValue::from_str(&content_type) {
+        res.headers_mut().insert(header::CONTENT_TYPE, v);
+    }
+    res.headers_mut().insert("idempotent-replayed", HeaderValue::from_static("true"));
+    res
+}
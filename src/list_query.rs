@@ -0,0 +1,259 @@
+// src/list_query.rs
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{types::ipnetwork::IpNetwork, Postgres, QueryBuilder};
+
+use crate::error::FieldError;
+
+/// Largest `limit` accepted by the node list.
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Values of `nodes.status`, for the `status` list filter.
+pub const NODE_STATUSES: &[&str] = &["online", "offline", "unknown", "cert_mismatch"];
+
+/// Query string of `GET /api/nodes` as sent. Everything is taken as text so
+/// that [`ListQuery::validate`] can report every bad parameter at once.
+#[derive(Deserialize, Default)]
+pub struct ListQuery {
+    pub protocol: Option<String>,
+    pub ip: Option<String>,
+    pub geo: Option<String>,
+    pub tag: Option<String>,
+    pub status: Option<String>,
+    pub after: Option<String>,
+    pub limit: Option<String>,
+    pub offset: Option<String>,
+    pub fields: Option<String>,
+    pub sort: Option<String>,
+    pub since: Option<String>,
+    pub envelope: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ListSort {
+    /// Online first, then by name and host; the order cursors encode.
+    Status,
+    /// Newest `protocol_version` first.
+    Protocol,
+}
+
+/// Validated node list parameters, defaults applied.
+pub struct ListFilter {
+    pub protocol: Option<String>,
+    pub ip: Option<IpNetwork>,
+    /// `Some(true)` keeps only nodes without GeoIP data.
+    pub geo_missing: Option<bool>,
+    pub tag: Option<String>,
+    pub statuses: Option<Vec<String>>,
+    /// `(status, name, host)` of the last node on the previous page.
+    pub after: Option<(String, String, String)>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+    pub fields: Option<Vec<String>>,
+    pub sort: ListSort,
+    /// Only nodes whose `updated_at` is later than this.
+    pub since: Option<DateTime<Utc>>,
+    /// `false` returns the bare node array, paging data in headers.
+    pub envelope: bool,
+}
+
+impl ListQuery {
+    /// Checks every parameter, collecting all problems. `header_protocol` is
+    /// `X-Protocol-Version`, used when the query has no `protocol`.
+    pub fn validate(self, header_protocol: Option<String>) -> Result<ListFilter, Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let ip = self.ip.and_then(|raw| match raw.parse() {
+            Ok(net) => Some(net),
+            Err(_) => {
+                errors.push(FieldError::new("ip", "must be an IP address or CIDR range"));
+                None
+            }
+        });
+        let geo_missing = match self.geo.as_deref() {
+            None => None,
+            Some("missing") => Some(true),
+            Some("present") => Some(false),
+            Some(_) => {
+                errors.push(FieldError::new("geo", "must be missing or present"));
+                None
+            }
+        };
+        let statuses = self.status.map(|raw| {
+            let statuses = split_list(&raw);
+            for unknown in statuses.iter().filter(|s| !NODE_STATUSES.contains(&s.as_str())) {
+                errors.push(FieldError::new(
+                    "status",
+                    format!("unknown status {unknown}, expected one of {}", NODE_STATUSES.join(",")),
+                ));
+            }
+            statuses
+        });
+        let after = self.after.and_then(|raw| {
+            let cursor = decode_cursor(&raw);
+            if cursor.is_none() {
+                errors.push(FieldError::new("after", "is not a valid cursor"));
+            }
+            cursor
+        });
+        let limit = self.limit.and_then(|raw| match raw.parse() {
+            Ok(n) if (1..=MAX_PAGE_SIZE).contains(&n) => Some(n),
+            _ => {
+                errors.push(FieldError::new("limit", format!("must be between 1 and {MAX_PAGE_SIZE}")));
+                None
+            }
+        });
+        let offset = match self.offset.map(|raw| raw.parse::<usize>()) {
+            None => 0,
+            Some(Ok(n)) => n,
+            Some(Err(_)) => {
+                errors.push(FieldError::new("offset", "must be a non-negative integer"));
+                0
+            }
+        };
+        let fields = self.fields.map(|raw| {
+            let fields = split_list(&raw);
+            let unknown: Vec<&str> = fields
+                .iter()
+                .map(String::as_str)
+                .filter(|f| !crate::NODE_FIELDS.contains(f))
+                .collect();
+            if !unknown.is_empty() {
+                errors.push(FieldError::new("fields", format!("unknown fields: {}", unknown.join(","))));
+            }
+            fields
+        });
+        let sort = match self.sort.as_deref() {
+            None | Some("status") => ListSort::Status,
+            Some("protocol") => ListSort::Protocol,
+            Some(_) => {
+                errors.push(FieldError::new("sort", "must be status or protocol"));
+                ListSort::Status
+            }
+        };
+        let since = self.since.and_then(|raw| match DateTime::parse_from_rfc3339(raw.trim()) {
+            Ok(t) => Some(t.with_timezone(&Utc)),
+            Err(_) => {
+                errors.push(FieldError::new("since", "must be an RFC 3339 timestamp"));
+                None
+            }
+        });
+        let envelope = match self.envelope.as_deref() {
+            None | Some("true") => true,
+            Some("false") => false,
+            Some(_) => {
+                errors.push(FieldError::new("envelope", "must be true or false"));
+                true
+            }
+        };
+        if after.is_some() && offset > 0 {
+            errors.push(FieldError::new("after", "cannot be combined with offset"));
+        }
+        if after.is_some() && sort == ListSort::Protocol {
+            // Cursors encode the status order; protocol order pages with offset.
+            errors.push(FieldError::new("after", "cannot be combined with sort=protocol"));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(ListFilter {
+            protocol: self.protocol.or(header_protocol),
+            ip,
+            geo_missing,
+            tag: self.tag.filter(|t| !t.is_empty()),
+            statuses,
+            after,
+            limit,
+            offset,
+            fields,
+            sort,
+            since,
+            envelope,
+        })
+    }
+}
+
+impl ListFilter {
+    /// Whether this is the plain, unfiltered list, the only one cached.
+    pub fn is_default(&self) -> bool {
+        self.protocol.is_none()
+            && self.ip.is_none()
+            && self.geo_missing.is_none()
+            && self.tag.is_none()
+            && self.statuses.is_none()
+            && self.fields.is_none()
+            && self.after.is_none()
+            && self.limit.is_none()
+            && self.offset == 0
+            && self.sort == ListSort::Status
+            && self.since.is_none()
+            && self.envelope
+    }
+
+    /// Listed nodes matching the SQL-side filters, in status order, after
+    /// the keyset cursor `after`, skipping `offset` rows and returning at
+    /// most `limit`. The protocol filter and sort are not applied here.
+    pub fn sql(
+        &self,
+        after: Option<&(String, String, String)>,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> QueryBuilder<'_, Postgres> {
+        let mut qb = QueryBuilder::new(format!(
+            "SELECT {} FROM nodes WHERE NOT suspended AND approved",
+            crate::NODE_COLUMNS
+        ));
+        if let Some(ip) = &self.ip {
+            qb.push(" AND ip <<= ").push_bind(ip);
+        }
+        if let Some(missing) = self.geo_missing {
+            qb.push(" AND (country_code IS NULL) = ").push_bind(missing);
+        }
+        if let Some(tag) = &self.tag {
+            qb.push(" AND tags @> ARRAY[").push_bind(tag).push("::text]");
+        }
+        if let Some((status, name, host)) = after {
+            qb.push(" AND (status < ")
+                .push_bind(status.clone())
+                .push(" OR (status = ")
+                .push_bind(status.clone())
+                .push(" AND (name, host) > (")
+                .push_bind(name.clone())
+                .push(", ")
+                .push_bind(host.clone())
+                .push(")))");
+        }
+        if let Some(since) = self.since {
+            qb.push(" AND updated_at > ").push_bind(since);
+        }
+        if let Some(statuses) = &self.statuses {
+            qb.push(" AND status = ANY(").push_bind(statuses).push(")");
+        }
+        qb.push(" ORDER BY status DESC, name ASC, host ASC");
+        if let Some(limit) = limit {
+            qb.push(" LIMIT ").push_bind(limit as i64);
+        }
+        if offset > 0 {
+            qb.push(" OFFSET ").push_bind(offset as i64);
+        }
+        qb
+    }
+}
+
+fn split_list(raw: &str) -> Vec<String> {
+    raw.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_owned).collect()
+}
+
+/// Opaque keyset cursor: the `(status, name, host)` sort key of the last
+/// node on a page, as base64url JSON.
+pub fn encode_cursor(status: String, name: String, host: String) -> String {
+    URL_SAFE_NO_PAD.encode(json!([status, name, host]).to_string())
+}
+
+fn decode_cursor(raw: &str) -> Option<(String, String, String)> {
+    let bytes = URL_SAFE_NO_PAD.decode(raw.trim()).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
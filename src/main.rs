@@ -1,253 +1,1113 @@
 // src/main.rs
+mod admin;
+mod audit;
+mod backpressure;
+mod cache;
 mod canon;
+mod cli;
+mod client_ip;
+mod config;
+mod content_type;
+mod crypto;
+mod dns;
+mod email;
+mod error;
+mod feed;
+mod gc;
+mod geojson;
+mod health;
+mod http_cache;
+mod idempotency;
+mod list_query;
+mod maintenance;
+mod pow;
+mod ratelimit;
+mod readiness;
+mod replay;
+mod schema;
+mod server;
+mod slowlog;
+mod snapshot;
+mod stats;
 mod types;
+mod uptime;
+mod validate;
+mod version;
 
 use axum::{
-    routing::{get, post},
+    extract::FromRef,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, patch, post},
     Json, Router,
 };
-use axum::http::StatusCode;
-use base64::{engine::general_purpose::STANDARD as B64, engine::general_purpose::URL_SAFE_NO_PAD, Engine};
-use chrono::{DateTime, Duration, Utc};
-use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use axum::http::{header, HeaderMap, StatusCode};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use crypto::{decode_b64, decode_b64_bounded, MAX_PUBKEY_B64_LEN, MAX_SIGNATURE_B64_LEN};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::Parser;
 use rand::RngCore;
 use serde_json::json;
 use sqlx::{PgPool, Row};
-use std::{net::{IpAddr, SocketAddr, ToSocketAddrs}, time::Duration as StdDuration};
-use tokio::net::TcpListener;
-use tower_http::{cors::CorsLayer, timeout::TimeoutLayer, trace::TraceLayer};
-use tracing::{error, info};
+use std::{net::{IpAddr, SocketAddr}, time::Duration as StdDuration};
+use tower_http::{
+    cors::CorsLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
+use tracing::{debug, error, info, warn};
 use types::*;
-use maxminddb::Reader;
-use maxminddb::geoip2::City;
+use std::collections::HashMap;
 use std::sync::Arc;
+use admin::AdminAuth;
+use backpressure::WriteLimiter;
+use cache::ListCache;
+use maintenance::Maintenance;
+use ratelimit::RateLimiter;
+use replay::{ReplayCache, ReplayClaim};
+use stats::StatusCounts;
+use config::Config;
+use dns::{Dns, DnsError};
+use email::Mailer;
+use error::ApiError;
+use health::HealthWorker;
+use list_query::{ListQuery, ListSort};
+
+/// Pool for read-only handlers: the replica when `DATABASE_URL_REPLICA` is
+/// set, otherwise the primary.
+#[derive(Clone)]
+struct ReadPool(PgPool);
+
+#[derive(Clone, FromRef)]
+struct AppState {
+    db: PgPool,
+    read_db: ReadPool,
+    list_cache: Arc<ListCache>,
+    admin: Arc<AdminAuth>,
+    maintenance: Arc<Maintenance>,
+    replay: Arc<ReplayCache>,
+    cfg: Arc<Config>,
+    dns: Arc<Dns>,
+    counts: Arc<StatusCounts>,
+    mailer: Arc<Mailer>,
+    health: Arc<HealthWorker>,
+    rate_limiter: Arc<RateLimiter>,
+    write_limiter: Arc<WriteLimiter>,
+    snapshot_signer: Arc<snapshot::SnapshotSigner>,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
-    tracing_subscriber::fmt().with_env_filter("info").init();
+
+    match cli::Cli::parse().command {
+        Some(cli::Command::Sign { key, payload, nonce, pow_difficulty }) => {
+            return cli::sign(&key, &payload, &nonce, pow_difficulty)
+        }
+        Some(cli::Command::Serve) | None => {}
+    }
+
+    // RUST_LOG=debug also shows per-node probe and DNS lines.
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
 
     let db = PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+    schema::check(&db).await?;
+    let read_db = match std::env::var("DATABASE_URL_REPLICA") {
+        Ok(url) if !url.is_empty() => {
+            info!("serving reads from replica");
+            ReadPool(PgPool::connect(&url).await?)
+        }
+        _ => ReadPool(db.clone()),
+    };
 
-    let db_clone = db.clone();
-    tokio::spawn(async move { health_worker(db_clone).await });
+    let client = http_client()?;
+    readiness::warmup(&db, &read_db.0, &client).await?;
+    let list_cache = Arc::new(ListCache::from_env());
+    let counts = Arc::new(StatusCounts::new());
+    let health = Arc::new(HealthWorker::new(
+        db.clone(),
+        client,
+        list_cache.clone(),
+        counts.clone(),
+    )?);
 
-    let app = Router::new()
-        .route("/api/registry/challenge", post(challenge))
-        .route("/api/registry/register", post(register))
+    tokio::spawn(health::health_worker(health.clone()));
+    tokio::spawn(stats::refresh_worker(db.clone(), counts.clone()));
+    tokio::spawn(gc::gc_worker(db.clone(), list_cache.clone(), counts.clone()));
+    let dns = Arc::new(Dns::from_env()?);
+    tokio::spawn(dns::refresh_worker(db.clone(), dns.clone(), list_cache.clone()));
+
+    let state = AppState {
+        db,
+        read_db,
+        list_cache,
+        admin: Arc::new(AdminAuth::from_env()),
+        maintenance: Arc::new(Maintenance::from_env()),
+        replay: Arc::new(ReplayCache::from_env()),
+        cfg: Arc::new(Config::from_env()?),
+        dns,
+        counts,
+        mailer: Arc::new(Mailer::from_env()?),
+        health,
+        rate_limiter: Arc::new(RateLimiter::from_env()),
+        write_limiter: Arc::new(WriteLimiter::from_env()),
+        snapshot_signer: Arc::new(snapshot::SnapshotSigner::from_env()?),
+    };
+
+    let rate_limit = middleware::from_fn_with_state(state.clone(), ratelimit::layer);
+    let writes = Router::new()
+        .route("/api/registry/challenge", post(challenge).route_layer(rate_limit.clone()))
+        .route("/api/registry/challenge/batch", post(challenge_batch))
+        .route(
+            "/api/registry/register",
+            post(register)
+                .route_layer(middleware::from_fn_with_state(state.clone(), idempotency::layer))
+                .route_layer(rate_limit),
+        )
         .route("/api/registry/heartbeat", post(heartbeat))
+        .route("/api/registry/heartbeat/batch", post(heartbeat_batch))
+        .route_layer(middleware::from_fn(content_type::require_json))
+        .route_layer(middleware::from_fn_with_state(state.clone(), backpressure::layer))
+        .route_layer(middleware::from_fn_with_state(state.clone(), maintenance::guard));
+
+    let reads = Router::new()
         .route("/api/nodes", get(list_nodes))
-        .with_state(db)
-        .layer(CorsLayer::permissive())
+        .route("/api/nodes/count", get(stats::node_count))
+        .route("/api/nodes/feed.xml", get(feed::status_feed))
+        .route("/api/nodes/geojson", get(geojson::nodes_geojson))
+        .route("/api/nodes/snapshot", get(snapshot::node_snapshot))
+        .route("/api/stats", get(stats::network_stats))
+        .route("/api/nodes/:host", get(get_node))
+        .route("/api/nodes/by-pubkey/:pubkey_b64", get(get_node_by_pubkey))
+        .route("/api/nodes/:host/uptime", get(uptime::node_uptime))
+        .route("/api/version", get(version_info))
+        .route_layer(middleware::map_response_with_state(
+            Arc::new(http_cache::ReadCaching::from_env()),
+            http_cache::public,
+        ));
+
+    // Long-lived responses (event streams, exports) are routed here. This
+    // router is merged after the request timeout is applied, so it is the
+    // one place that timeout does not reach; add `cors_layer()` to it when
+    // its routes are under /api.
+    let streaming: Router<AppState> = Router::new();
+
+    let app = Router::new()
+        .merge(writes)
+        .merge(reads)
+        .route("/api/registry/verify-email", get(email::verify_email))
+        .route(
+            "/api/registry/canon",
+            post(canon_preview).route_layer(middleware::from_fn(content_type::require_json)),
+        )
+        .route(
+            "/api/admin/maintenance",
+            post(admin::set_maintenance).route_layer(middleware::from_fn(content_type::require_json)),
+        )
+        .route("/api/admin/geoip/rebuild", post(admin::rebuild_geoip))
+        .route("/api/admin/health-worker", get(admin::health_worker_status))
+        .route("/api/admin/health-tick", post(admin::health_tick))
+        .route(
+            "/api/admin/nodes/:host",
+            patch(admin::edit_node).route_layer(middleware::from_fn(content_type::require_json)),
+        )
+        .route("/api/admin/nodes/:host/suspend", post(admin::suspend_node))
+        .route("/api/admin/nodes/:host/unsuspend", post(admin::unsuspend_node))
+        .route("/api/admin/pending", get(admin::pending_nodes))
+        .route("/api/admin/audit", get(admin::audit_log))
+        .route("/api/admin/nodes/:host/approve", post(admin::approve_node))
+        // Only the browser-facing /api routes get CORS; /metrics is for scrapers.
+        .layer(cors_layer())
+        .route("/metrics", get(stats::metrics))
+        .route("/readyz", get(readiness::readyz))
         .layer(TimeoutLayer::new(StdDuration::from_secs(10)))
-        .layer(TraceLayer::new_for_http());
+        .merge(streaming)
+        .with_state(state)
+        .layer(middleware::from_fn(error::json_fallback_errors))
+        .layer(middleware::map_response(http_cache::no_store_by_default))
+        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn_with_state(
+            Arc::new(slowlog::SlowRequests::from_env()),
+            slowlog::layer,
+        ))
+        // Outermost, so every layer above sees the ID; one sent by the client
+        // or a proxy is kept.
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
 
     let addr: SocketAddr = SocketAddr::new("0.0.0.0".parse().unwrap(), 8080);
     info!("registry listening on {addr}");
-    let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
-    Ok(())
+    let opts = server::ServerOptions::from_env();
+    let listener = server::bind(addr, &opts)?;
+    server::serve(listener, app, opts).await
 }
 
 // ---------- API HANDLERS ---------- //
 
 async fn challenge(
     axum::extract::State(db): axum::extract::State<PgPool>,
+    axum::extract::State(cfg): axum::extract::State<Arc<Config>>,
     Json(req): Json<ChallengeReq>,
-) -> Result<Json<ChallengeRes>, (StatusCode, String)> {
+) -> Result<Json<ChallengeRes>, ApiError> {
+    issue_challenge(&db, &cfg, &req).await.map(Json)
+}
+
+/// Challenges for many keys at once. Each entry is issued and rate limited
+/// like a single challenge request from the same client.
+async fn challenge_batch(
+    axum::extract::State(db): axum::extract::State<PgPool>,
+    axum::extract::State(cfg): axum::extract::State<Arc<Config>>,
+    axum::extract::State(limiter): axum::extract::State<Arc<RateLimiter>>,
+    axum::extract::ConnectInfo(peer): axum::extract::ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(reqs): Json<Vec<ChallengeReq>>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if reqs.len() > cfg.challenge_batch_max {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("at most {} challenges per batch", cfg.challenge_batch_max),
+        ));
+    }
+
+    let ip = cfg.proxies.client_ip(&headers, peer);
+    let mut results = Vec::with_capacity(reqs.len());
+    for req in &reqs {
+        let issued = match ratelimit::take(&limiter, ip) {
+            Ok(()) => issue_challenge(&db, &cfg, req).await,
+            Err(secs) => Err(ApiError::too_many_requests("rate limit exceeded", secs)),
+        };
+        results.push(match issued {
+            Ok(res) => {
+                let mut result = json!(res);
+                result["pubkey_b64"] = json!(req.pubkey_b64);
+                result["ok"] = json!(true);
+                result
+            }
+            Err(e) => {
+                let mut result = json!({
+                    "pubkey_b64": req.pubkey_b64,
+                    "ok": false,
+                    "status": e.status.as_u16(),
+                    "error": e.message,
+                });
+                if let Some(secs) = e.retry_after {
+                    result["retry_after"] = json!(secs);
+                }
+                result
+            }
+        });
+    }
+    Ok(Json(json!({ "results": results })))
+}
+
+async fn issue_challenge(db: &PgPool, cfg: &Config, req: &ChallengeReq) -> Result<ChallengeRes, ApiError> {
     if req.pubkey_b64.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "pubkey_b64 required".into()));
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "pubkey_b64 required"));
+    }
+    if req.pubkey_b64.len() > MAX_PUBKEY_B64_LEN {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("pubkey_b64 is too long (max {MAX_PUBKEY_B64_LEN} characters)"),
+        ));
     }
+    let alg = req.alg.as_deref().unwrap_or(crypto::DEFAULT_ALG);
+    crypto::scheme(Some(alg))?;
 
     let nonce = gen_nonce();
     let exp: DateTime<Utc> = Utc::now() + Duration::minutes(5);
 
-    sqlx::query("INSERT INTO challenges (nonce, pubkey_b64, expires_at) VALUES ($1,$2,$3)")
-        .bind(&nonce)
-        .bind(&req.pubkey_b64)
-        .bind(exp)
-        .execute(&db)
-        .await
-        .map_err(internal)?;
+    // The difficulty is stored with the challenge so changing POW_DIFFICULTY
+    // does not invalidate challenges already handed out.
+    sqlx::query(
+        "INSERT INTO challenges (nonce, pubkey_b64, alg, expires_at, pow_difficulty) VALUES ($1,$2,$3,$4,$5)",
+    )
+    .bind(&nonce)
+    .bind(&req.pubkey_b64)
+    .bind(alg)
+    .bind(exp)
+    .bind(cfg.pow_difficulty as i32)
+    .execute(db)
+    .await
+    .map_err(internal)?;
 
-    Ok(Json(ChallengeRes {
+    Ok(ChallengeRes {
         nonce,
         expires_at: exp.to_rfc3339(),
-    }))
+        pow_difficulty: (cfg.pow_difficulty > 0).then_some(cfg.pow_difficulty),
+    })
 }
 
 async fn register(
-    axum::extract::State(db): axum::extract::State<PgPool>,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::ConnectInfo(peer): axum::extract::ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(req): Json<RegisterReq>,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    use core::convert::TryFrom;
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let AppState { db, list_cache, counts, replay, cfg, dns, mailer, .. } = state;
 
-    let row = sqlx::query("SELECT pubkey_b64, expires_at FROM challenges WHERE nonce=$1")
+    let alg = req.alg.as_deref().unwrap_or(crypto::DEFAULT_ALG);
+    let scheme = crypto::scheme(Some(alg))?;
+    let pubkey = decode_b64_bounded("pubkey_b64", &req.pubkey_b64, MAX_PUBKEY_B64_LEN)?;
+    let sig_bytes = decode_b64_bounded("signature_b64", &req.signature_b64, MAX_SIGNATURE_B64_LEN)?;
+    let row = sqlx::query("SELECT pubkey_b64, alg, expires_at, pow_difficulty FROM challenges WHERE nonce=$1")
         .bind(&req.nonce)
         .fetch_optional(&db)
         .await
         .map_err(internal)?;
     let Some(row) = row else {
-        return Err((StatusCode::BAD_REQUEST, "invalid/expired nonce".into()));
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid/expired nonce"));
     };
     let chall_pub: String = row.get("pubkey_b64");
+    if row.get::<String, _>("alg") != alg {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "alg mismatch"));
+    }
     let chall_exp: DateTime<Utc> = row.get("expires_at");
     if chall_exp < Utc::now() {
-        return Err((StatusCode::BAD_REQUEST, "expired nonce".into()));
-    }
-    if chall_pub != req.pubkey_b64 {
-        return Err((StatusCode::BAD_REQUEST, "pubkey mismatch".into()));
-    }
-
-    let canon = canon::canonical_json_string(&req.payload);
-    let message = [canon.as_bytes(), req.nonce.as_bytes()].concat();
-
-    let sig_bytes = B64.decode(&req.signature_b64).map_err(badreq)?;
-    let sig = Signature::from_slice(&sig_bytes)
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid signature: {e}")))?;
-    let vk_bytes = B64.decode(&req.pubkey_b64).map_err(badreq)?;
-    let vk = VerifyingKey::try_from(&vk_bytes[..])
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid pubkey: {e}")))?;
-    vk.verify(&message, &sig)
-        .map_err(|_| (StatusCode::UNAUTHORIZED, "bad signature".into()))?;
-
-    let name = req.payload.get("name").and_then(|v| v.as_str()).ok_or(bad("name"))?;
-    let host = req.payload.get("host").and_then(|v| v.as_str()).ok_or(bad("host"))?;
-    let api = req
-        .payload
-        .get("api_base_url")
-        .and_then(|v| v.as_str())
-        .ok_or(bad("api_base_url"))?;
-    let proto = req
-        .payload
-        .get("protocol_version")
-        .and_then(|v| v.as_str())
-        .ok_or(bad("protocol_version"))?;
-    let features = req.payload.get("features").cloned().unwrap_or(json!({}));
-    let email = req
-        .payload
-        .get("contact_email")
-        .and_then(|v| v.as_str())
-        .unwrap_or_default();
-
-    let ip = resolve_ip(host).await.ok();
-    println!("Resolved IP for host {host}: {:?}", ip);
-    let ip_parsed: IpAddr = match ip {
-        Some(ref ip_str) => ip_str.parse().map_err(|_| bad("could not parse resolved IP"))?,
-        None => return Err(bad("could not resolve host")),
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "expired nonce"));
+    }
+    let expected = decode_b64(&chall_pub).ok();
+    if expected.as_deref() != Some(&pubkey[..]) {
+        // Fingerprints of the decoded keys let a client spot an encoding or
+        // key mix-up without either key being echoed back.
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "pubkey mismatch")
+            .code("pubkey_mismatch")
+            .details(json!({
+                "expected_fingerprint": expected.as_deref().map(crypto::fingerprint),
+                "provided_fingerprint": crypto::fingerprint(&pubkey),
+                "provided_length": pubkey.len(),
+            })));
+    }
+    // Checked before the signature: a failed proof costs the server one hash.
+    let difficulty = row.get::<i32, _>("pow_difficulty") as u32;
+    if difficulty > 0 {
+        let pow_nonce = req.pow_nonce.as_deref().unwrap_or_default();
+        if pow_nonce.is_empty() || pow_nonce.len() > pow::MAX_NONCE_LEN {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "pow_nonce required").code("pow_required"));
+        }
+        if !pow::verify(&req.nonce, pow_nonce, difficulty) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("pow_nonce does not meet difficulty {difficulty}"),
+            )
+            .code("pow_invalid"));
+        }
+    }
+
+    let message = canon::register_message(&req.payload, &req.nonce);
+
+    scheme.verify(&pubkey, &message, &sig_bytes)?;
+    // The signed payload must name the key itself, so the outer pubkey_b64
+    // cannot be swapped for another key that happens to verify.
+    if !validate::pubkey_bound(&req.payload, &pubkey) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "pubkey not bound in payload"));
+    }
+    // Released again if anything below fails, so the same signed request
+    // can be retried.
+    let replay_claim = replay
+        .claim("register", &sig_bytes)
+        .ok_or_else(|| ApiError::new(StatusCode::CONFLICT, "replay detected"))?;
+
+    let node = validate::node_payload(&req.payload, &cfg.required_payload_fields, &cfg.feature_limits)
+        .map_err(ApiError::validation)?;
+    let (name, host, api, proto) =
+        (&node.name[..], &node.host[..], &node.api_base_url[..], &node.protocol_version[..]);
+    if !cfg.allow_mismatched_api_host && !validate::api_host_matches(host, api) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "api_base_url host mismatch"));
+    }
+    let name_key = validate::name_key(name);
+    if let Some(pattern) = cfg.reserved_names.iter().find(|p| name_key.contains(p.as_str())) {
+        warn!("node {host}: rejected name {name:?}, matches reserved pattern {pattern:?}");
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "name not permitted"));
+    }
+    if let Some((min_raw, min)) = &cfg.min_register_protocol {
+        let v = version::parse(proto).ok_or(bad("protocol_version"))?;
+        if version::compare(&v, min).is_lt() {
+            return Err(ApiError::new(
+                StatusCode::UPGRADE_REQUIRED,
+                format!("protocol_version must be at least {min_raw}"),
+            ));
+        }
+    }
+    let features = node.features.clone();
+    let email = node.contact_email.clone().unwrap_or_default();
+
+    // Onion services are only reachable through Tor and have no IP.
+    let onion = dns::is_onion(host);
+    let ip_parsed: Option<IpAddr> = if onion {
+        None
+    } else {
+        let ip = match dns.resolve(host).await {
+            Ok(ip) => ip,
+            Err(DnsError::Timeout) => {
+                return Err(ApiError::new(StatusCode::GATEWAY_TIMEOUT, "dns timeout"))
+            }
+            Err(DnsError::NotFound(e)) => {
+                info!("could not resolve host {host}: {e}");
+                return Err(bad("could not resolve host"));
+            }
+        };
+        debug!(%host, %ip, "resolved host");
+        Some(ip)
     };
+    // Off by default: nodes behind NAT may only be reachable some other way.
+    if let (Some(timeout), false) = (cfg.reachability_timeout, onion) {
+        if let Err(e) = health::api_reachable(&dns, api, timeout).await {
+            info!("node {host}: api_base_url not reachable ({e})");
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "api_base_url not reachable")
+                .code("unreachable")
+                .details(json!({ "reason": e })));
+        }
+    }
 
-    if let Some(row) = sqlx::query("SELECT pubkey FROM nodes WHERE host=$1")
+    // Everything above is checked without holding a connection. From here
+    // the nonce claim, the host check and the upsert commit together or not
+    // at all: deleting the challenge row is the claim, so of two concurrent
+    // requests with one nonce only the first gets past it, and any error
+    // below rolls back and leaves the nonce usable.
+    let mut tx = db.begin().await.map_err(internal)?;
+    let claimed = sqlx::query("DELETE FROM challenges WHERE nonce=$1 AND expires_at >= now()")
+        .bind(&req.nonce)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal)?;
+    if claimed.rows_affected() == 0 {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid/expired nonce"));
+    }
+
+    let mut email_changed = true;
+    let mut kept_name: Option<String> = None;
+    let mut warnings: Vec<String> = Vec::new();
+    let existing = sqlx::query("SELECT pubkey, name, contact_email FROM nodes WHERE host=$1 FOR UPDATE")
         .bind(host)
-        .fetch_optional(&db)
+        .fetch_optional(&mut *tx)
         .await
-        .map_err(internal)?
-    {
+        .map_err(internal)?;
+    if existing.is_none() && cfg.max_total_nodes > 0 {
+        // Serializes new hosts while the cap is on, so two registrations
+        // cannot both take the last slot.
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MAX_TOTAL_NODES_LOCK)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal)?;
+        let total: i64 = sqlx::query_scalar("SELECT count(*) FROM nodes")
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(internal)?;
+        if total >= cfg.max_total_nodes {
+            warn!("registry full ({total} of MAX_TOTAL_NODES={}), rejected new host {host}", cfg.max_total_nodes);
+            return Err(ApiError::new(StatusCode::INSUFFICIENT_STORAGE, "registry full").code("registry_full"));
+        }
+    }
+    if let Some(row) = existing {
+        email_changed = row.get::<Option<String>, _>("contact_email").as_deref() != Some(&email[..]);
         let existing_pubkey: Vec<u8> = row.get("pubkey");
-        let new_pubkey = B64.decode(&req.pubkey_b64).map_err(badreq)?;
-        if existing_pubkey != new_pubkey {
-            return Err((StatusCode::FORBIDDEN, "host already registered with another key".into()));
+        if existing_pubkey != pubkey {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "host already registered with another key"));
+        }
+        let old_name: String = row.get("name");
+        if old_name != name {
+            if cfg.name_change_requires_flag && !node.allow_rename {
+                warn!("node {host}: ignoring rename {old_name:?} -> {name:?} without allow_rename");
+                warnings.push(format!("name change ignored, keeping {old_name:?}; set allow_rename to rename"));
+                kept_name = Some(old_name);
+            } else {
+                info!("node {host} renamed {old_name:?} -> {name:?}");
+            }
         }
     }
-    sqlx::query(
+    // The WHERE on the update keeps a host registered concurrently under
+    // another key from being taken over: the losing upsert returns no row.
+    let approved: Option<bool> = sqlx::query_scalar(
         r#"
-        INSERT INTO nodes (name, host, ip, api_base_url, pubkey, protocol_version, features, contact_email, status)
-        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,'unknown')
+        INSERT INTO nodes (name, host, ip, api_base_url, pubkey, protocol_version, features, contact_email,
+                           max_connections, weight, tags, alg, tls_fingerprint, approved, is_onion,
+                           description, software_version, operator, status)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16,$17,$18,'unknown')
         ON CONFLICT(host) DO UPDATE
           SET name=EXCLUDED.name,
               ip=EXCLUDED.ip,
               api_base_url=EXCLUDED.api_base_url,
               pubkey=EXCLUDED.pubkey,
+              alg=EXCLUDED.alg,
               protocol_version=EXCLUDED.protocol_version,
               features=EXCLUDED.features,
-              contact_email=EXCLUDED.contact_email
+              contact_email=EXCLUDED.contact_email,
+              max_connections=EXCLUDED.max_connections,
+              weight=EXCLUDED.weight,
+              tags=EXCLUDED.tags,
+              tls_fingerprint=EXCLUDED.tls_fingerprint,
+              description=EXCLUDED.description,
+              software_version=EXCLUDED.software_version,
+              operator=EXCLUDED.operator,
+              email_verified = nodes.email_verified
+                AND nodes.contact_email IS NOT DISTINCT FROM EXCLUDED.contact_email,
+              updated_at = now()
+          WHERE nodes.pubkey = EXCLUDED.pubkey
+        RETURNING approved
         "#,
     )
-    .bind(name)
+    .bind(kept_name.as_deref().unwrap_or(name))
     .bind(host)
     .bind(ip_parsed)
     .bind(api)
-    .bind(B64.decode(&req.pubkey_b64).map_err(badreq)?)
+    .bind(&pubkey)
     .bind(proto)
     .bind(features)
-    .bind(email)
-    .execute(&db)
+    .bind(&email)
+    .bind(node.max_connections)
+    .bind(node.weight)
+    .bind(&node.tags)
+    .bind(alg)
+    .bind(&node.tls_fingerprint)
+    .bind(!cfg.approval_required)
+    .bind(onion)
+    .bind(&node.description)
+    .bind(&node.software_version)
+    .bind(&node.operator)
+    .fetch_optional(&mut *tx)
     .await
     .map_err(internal)?;
+    let Some(approved) = approved else {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "host already registered with another key"));
+    };
+    tx.commit().await.map_err(internal)?;
+    replay_claim.keep();
 
-    sqlx::query("DELETE FROM challenges WHERE nonce=$1")
-        .bind(&req.nonce)
-        .execute(&db)
-        .await
-        .ok();
+    if mailer.required && email_changed && !email.is_empty() {
+        mailer.start_verification(&db, host, &email).await.map_err(internal)?;
+    }
+    list_cache.invalidate();
+    counts.poke();
+    info!(
+        "node {host} registered from {}{}",
+        cfg.proxies.client_ip(&headers, peer),
+        if approved { "" } else { ", pending approval" }
+    );
 
-    Ok(Json(json!({"ok": true})))
+    let mut res = json!({"ok": true});
+    if !approved {
+        res["pending_approval"] = json!(true);
+    }
+    if !warnings.is_empty() {
+        res["warnings"] = json!(warnings);
+    }
+    Ok(Json(res))
 }
 
 async fn heartbeat(
     axum::extract::State(db): axum::extract::State<PgPool>,
+    axum::extract::State(list_cache): axum::extract::State<Arc<ListCache>>,
+    axum::extract::State(counts): axum::extract::State<Arc<StatusCounts>>,
+    axum::extract::State(replay): axum::extract::State<Arc<ReplayCache>>,
+    axum::extract::State(cfg): axum::extract::State<Arc<Config>>,
     Json(req): Json<HeartbeatReq>,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    use core::convert::TryFrom;
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let (metrics, replay_claim) = verify_heartbeat(&db, &replay, &req).await?;
 
-    let message = [req.host.as_bytes(), req.nonce.as_bytes()].concat();
+    let throttled = record_heartbeats(
+        &db,
+        std::slice::from_ref(&req.host),
+        &[metrics],
+        cfg.min_heartbeat_interval_secs,
+    )
+    .await?;
+    if let Some(&secs) = throttled.get(&req.host) {
+        return Err(ApiError::too_many_requests("heartbeat too soon", secs));
+    }
+    replay_claim.keep();
+    list_cache.invalidate();
+    counts.poke();
 
-    let sig_bytes = B64.decode(&req.signature_b64).map_err(badreq)?;
-    let sig = Signature::from_slice(&sig_bytes)
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid signature: {e}")))?;
-    let vk_bytes = B64.decode(&req.pubkey_b64).map_err(badreq)?;
-    let vk = VerifyingKey::try_from(&vk_bytes[..])
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid pubkey: {e}")))?;
+    Ok(Json(json!({"ok": true})))
+}
 
-    vk.verify(&message, &sig)
-        .map_err(|_| (StatusCode::UNAUTHORIZED, "bad signature".into()))?;
+/// Verifies many hosts' heartbeats in one request. Each entry is checked on
+/// its own and the accepted hosts are updated together in one transaction.
+async fn heartbeat_batch(
+    axum::extract::State(db): axum::extract::State<PgPool>,
+    axum::extract::State(list_cache): axum::extract::State<Arc<ListCache>>,
+    axum::extract::State(counts): axum::extract::State<Arc<StatusCounts>>,
+    axum::extract::State(replay): axum::extract::State<Arc<ReplayCache>>,
+    axum::extract::State(cfg): axum::extract::State<Arc<Config>>,
+    Json(reqs): Json<Vec<HeartbeatReq>>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if reqs.len() > cfg.heartbeat_batch_max {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("at most {} heartbeats per batch", cfg.heartbeat_batch_max),
+        ));
+    }
 
-    let now = Utc::now();
-    sqlx::query("UPDATE nodes SET last_seen_at=$1, status='online' WHERE host=$2")
-        .bind(now)
-        .bind(&req.host)
-        .execute(&db)
-        .await
-        .map_err(internal)?;
+    let mut accepted: Vec<String> = Vec::new();
+    let mut accepted_metrics: Vec<Option<serde_json::Value>> = Vec::new();
+    let mut replay_claims = Vec::new();
+    let mut errors: HashMap<usize, ApiError> = HashMap::new();
+    for (i, req) in reqs.iter().enumerate() {
+        match verify_heartbeat(&db, &replay, req).await {
+            Ok((metrics, claim)) => {
+                accepted.push(req.host.clone());
+                accepted_metrics.push(metrics);
+                replay_claims.push((i, claim));
+            }
+            Err(e) => {
+                errors.insert(i, e);
+            }
+        }
+    }
 
-    Ok(Json(json!({"ok": true})))
+    if !accepted.is_empty() {
+        let mut tx = db.begin().await.map_err(internal)?;
+        let throttled =
+            record_heartbeats(&mut *tx, &accepted, &accepted_metrics, cfg.min_heartbeat_interval_secs).await?;
+        tx.commit().await.map_err(internal)?;
+        for (i, req) in reqs.iter().enumerate() {
+            if let (false, Some(&secs)) = (errors.contains_key(&i), throttled.get(&req.host)) {
+                errors.insert(i, ApiError::too_many_requests("heartbeat too soon", secs));
+            }
+        }
+        // Throttled entries give their signature back for a later retry.
+        for (i, claim) in replay_claims {
+            if !errors.contains_key(&i) {
+                claim.keep();
+            }
+        }
+        if throttled.len() < accepted.len() {
+            list_cache.invalidate();
+            counts.poke();
+        }
+    }
+
+    let results: Vec<serde_json::Value> = reqs
+        .iter()
+        .enumerate()
+        .map(|(i, req)| match errors.remove(&i) {
+            None => json!({ "host": req.host, "ok": true }),
+            Some(e) => {
+                let mut result = json!({
+                    "host": req.host,
+                    "ok": false,
+                    "status": e.status.as_u16(),
+                    "error": e.message,
+                });
+                if let Some(secs) = e.retry_after {
+                    result["retry_after"] = json!(secs);
+                }
+                result
+            }
+        })
+        .collect();
+    Ok(Json(json!({ "results": results })))
 }
 
-async fn list_nodes(
-    axum::extract::State(db): axum::extract::State<PgPool>,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+/// Marks `hosts` as seen now and stores each one's entry of `metrics`,
+/// except hosts whose previous heartbeat is less than `min_interval_secs`
+/// old. Returns the skipped hosts with the seconds until each may heartbeat
+/// again.
+async fn record_heartbeats(
+    db: impl sqlx::PgExecutor<'_>,
+    hosts: &[String],
+    metrics: &[Option<serde_json::Value>],
+    min_interval_secs: u64,
+) -> Result<HashMap<String, u64>, ApiError> {
+    let now = Utc::now();
     let rows = sqlx::query(
-        "SELECT name, host, ip::text AS ip, api_base_url, protocol_version, features,
-                country_code, country_name, last_seen_at, last_latency_ms, status
-         FROM nodes
-         ORDER BY status DESC, name ASC",
+        r#"
+        WITH target AS (
+            SELECT n.host, n.last_heartbeat_at, i.metrics
+            FROM nodes n
+            JOIN unnest($2::text[], $4::jsonb[]) AS i(host, metrics) ON i.host = n.host
+            FOR UPDATE OF n
+        ), updated AS (
+            UPDATE nodes n
+            SET last_seen_at = $1, last_heartbeat_at = $1, status = 'online',
+                self_reported_metrics = t.metrics
+            FROM target t
+            WHERE n.host = t.host
+              AND (t.last_heartbeat_at IS NULL OR t.last_heartbeat_at <= $1 - make_interval(secs => $3))
+            RETURNING n.host
+        )
+        SELECT t.host, t.last_heartbeat_at
+        FROM target t
+        WHERE t.host NOT IN (SELECT host FROM updated)
+        "#,
     )
-    .fetch_all(&db)
+    .bind(now)
+    .bind(hosts)
+    .bind(min_interval_secs as f64)
+    .bind(metrics)
+    .fetch_all(db)
     .await
     .map_err(internal)?;
 
-    let nodes: Vec<serde_json::Value> = rows
-        .into_iter()
+    Ok(rows
+        .iter()
         .map(|r| {
-            json!({
-                "name": r.get::<String,_>("name"),
-                "host": r.get::<String,_>("host"),
-                "ip": r.get::<Option<String>,_>("ip"),
-                "api_base_url": r.get::<String,_>("api_base_url"),
-                "protocol_version": r.get::<String,_>("protocol_version"),
-                "features": r.get::<serde_json::Value,_>("features"),
-                "country_code": r.get::<Option<String>,_>("country_code"),
-                "country_name": r.get::<Option<String>,_>("country_name"),
-                "last_seen_at": r.get::<Option<DateTime<Utc>>,_>("last_seen_at"),
-                "last_latency_ms": r.get::<Option<i32>,_>("last_latency_ms"),
-                "status": r.get::<String,_>("status"),
-            })
+            let last: DateTime<Utc> = r.get("last_heartbeat_at");
+            let wait = last + Duration::seconds(min_interval_secs as i64) - now;
+            // Rounded up, so a client honoring it is never early.
+            let secs = (wait.num_milliseconds().max(0) as u64).div_ceil(1000).max(1);
+            (r.get("host"), secs)
         })
+        .collect())
+}
+
+/// Checks a heartbeat's signature against the host's registered key and
+/// claims it in the replay cache. Returns its validated metrics and the
+/// claim, to be kept once the heartbeat is recorded.
+async fn verify_heartbeat<'a>(
+    db: &PgPool,
+    replay: &'a ReplayCache,
+    req: &HeartbeatReq,
+) -> Result<(Option<serde_json::Value>, ReplayClaim<'a>), ApiError> {
+    let metrics = req
+        .metrics
+        .as_ref()
+        .map(validate::heartbeat_metrics)
+        .transpose()
+        .map_err(ApiError::validation)?;
+    let message = canon::heartbeat_message(&req.host, &req.nonce, req.metrics.as_ref());
+
+    let sig_bytes = decode_b64_bounded("signature_b64", &req.signature_b64, MAX_SIGNATURE_B64_LEN)?;
+    let claimed_pubkey = decode_b64_bounded("pubkey_b64", &req.pubkey_b64, MAX_PUBKEY_B64_LEN)?;
+
+    // Verify against the key the host registered with, never the key the
+    // request brings along, so nobody can heartbeat for a host they don't own.
+    let row = sqlx::query("SELECT pubkey, alg FROM nodes WHERE host=$1")
+        .bind(&req.host)
+        .fetch_optional(db)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "node not found"))?;
+    let stored_pubkey: Vec<u8> = row.get("pubkey");
+    let alg: String = row.get("alg");
+    // Checked before the comparison: a broken stored key is a data problem
+    // to surface, not a 403 to blame on the client.
+    let scheme = crypto::scheme(Some(&alg))
+        .map_err(|_| corrupt_node_key(&req.host, &format!("unsupported alg {alg:?}")))?;
+    scheme
+        .check_key(&stored_pubkey)
+        .map_err(|e| corrupt_node_key(&req.host, &format!("{} bytes, {e}", stored_pubkey.len())))?;
+    if stored_pubkey != claimed_pubkey {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "pubkey does not match registered key"));
+    }
+    scheme.verify(&stored_pubkey, &message, &sig_bytes)?;
+    let claim = replay
+        .claim("heartbeat", &sig_bytes)
+        .ok_or_else(|| ApiError::new(StatusCode::CONFLICT, "replay detected"))?;
+    Ok((metrics, claim))
+}
+
+async fn list_nodes(
+    axum::extract::State(ReadPool(db)): axum::extract::State<ReadPool>,
+    axum::extract::State(list_cache): axum::extract::State<Arc<ListCache>>,
+    axum::extract::State(cfg): axum::extract::State<Arc<Config>>,
+    axum::extract::Query(query): axum::extract::Query<ListQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // An explicit `protocol` query parameter wins over the header.
+    let header_protocol = headers
+        .get("x-protocol-version")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    let filter = query.validate(header_protocol).map_err(ApiError::validation)?;
+    let filtered = !filter.is_default();
+
+    if !filtered {
+        if let Some(body) = list_cache.get() {
+            return negotiated_body(body, &headers);
+        }
+    }
+
+    // Taken before the query on the database clock, which also sets
+    // `updated_at`, so a change racing this request shows up on the next
+    // `since` poll rather than being skipped.
+    let server_time: DateTime<Utc> =
+        sqlx::query_scalar("SELECT now()").fetch_one(&db).await.map_err(internal)?;
+    // One row more than the page size tells whether there is a next page.
+    let take = filter.limit.map(|n| n + 1);
+    let by_protocol = filter.sort == ListSort::Protocol;
+    let mut page: Vec<sqlx::postgres::PgRow> = match (by_protocol, filter.protocol.as_deref()) {
+        (false, None) => filter
+            .sql(filter.after.as_ref(), take, filter.offset)
+            .build()
+            .fetch_all(&db)
+            .await
+            .map_err(internal)?,
+        (false, Some(wanted)) => protocol_matches(&db, &filter, cfg.protocol_compat, wanted, take).await?,
+        (true, wanted) => {
+            // Versions are compared parsed, which SQL cannot order by, so
+            // this order is fetched whole and paged here.
+            let rows = filter.sql(None, None, 0).build().fetch_all(&db).await.map_err(internal)?;
+            let rows = rows
+                .into_iter()
+                .filter(|r| {
+                    wanted.is_none_or(|w| cfg.protocol_compat.matches(&r.get::<String, _>("protocol_version"), w))
+                })
+                .collect();
+            sort_by_protocol(rows)
+                .into_iter()
+                .skip(filter.offset)
+                .take(take.unwrap_or(usize::MAX))
+                .collect()
+        }
+    };
+    let next_cursor = match filter.limit {
+        Some(n) if page.len() > n && by_protocol => {
+            page.truncate(n);
+            None
+        }
+        Some(n) if page.len() > n => {
+            page.truncate(n);
+            page.last().map(|r| list_query::encode_cursor(r.get("status"), r.get("name"), r.get("host")))
+        }
+        _ => None,
+    };
+
+    let nodes: Vec<serde_json::Value> = page
+        .iter()
+        .map(node_json)
+        .map(|node| match &filter.fields {
+            Some(fields) => project(node, fields),
+            None => node,
+        })
+        .collect();
+
+    if !filter.envelope {
+        // The bare array has nowhere to put paging data, so it goes in headers.
+        let body = axum::body::Bytes::from(serde_json::to_vec(&nodes).map_err(internal)?);
+        let mut res = negotiated_body(body, &headers)?;
+        let server_time = server_time.to_rfc3339_opts(SecondsFormat::Micros, true);
+        res.headers_mut().insert("x-server-time", server_time.parse().map_err(internal)?);
+        if let Some(cursor) = next_cursor {
+            res.headers_mut().insert("x-next-cursor", cursor.parse().map_err(internal)?);
+        }
+        return Ok(res);
+    }
+    let mut res = json!({ "nodes": nodes, "server_time": server_time });
+    if filter.limit.is_some() {
+        res["next_cursor"] = json!(next_cursor);
+    }
+    let body = axum::body::Bytes::from(serde_json::to_vec(&res).map_err(internal)?);
+    if !filtered {
+        list_cache.put(body.clone());
+    }
+    negotiated_body(body, &headers)
+}
+
+/// Rows of the status-ordered list whose `protocol_version` matches
+/// `wanted`, after `filter.offset` matches and at most `take` of them. The
+/// match runs in Rust, so rows are read in keyset batches larger than
+/// needed until enough match or the list ends.
+async fn protocol_matches(
+    db: &PgPool,
+    filter: &list_query::ListFilter,
+    compat: version::ProtocolCompat,
+    wanted: &str,
+    take: Option<usize>,
+) -> Result<Vec<sqlx::postgres::PgRow>, ApiError> {
+    let needed = take.map(|n| filter.offset + n);
+    let batch = needed.map(|n| (n * 2).clamp(100, 2000));
+    let mut after = filter.after.clone();
+    let mut matches = Vec::new();
+    loop {
+        let rows = filter.sql(after.as_ref(), batch, 0).build().fetch_all(db).await.map_err(internal)?;
+        let exhausted = batch.is_none_or(|b| rows.len() < b);
+        if let Some(last) = rows.last() {
+            after = Some((last.get("status"), last.get("name"), last.get("host")));
+        }
+        matches.extend(
+            rows.into_iter()
+                .filter(|r| compat.matches(&r.get::<String, _>("protocol_version"), wanted)),
+        );
+        if exhausted || needed.is_some_and(|n| matches.len() >= n) {
+            break;
+        }
+    }
+    Ok(matches.into_iter().skip(filter.offset).take(take.unwrap_or(usize::MAX)).collect())
+}
+
+/// Newest `protocol_version` first, using the same parsing as the protocol
+/// filter; unparseable versions go last. The sort is stable, so ties keep the
+/// status, name, host order.
+fn sort_by_protocol(rows: Vec<sqlx::postgres::PgRow>) -> Vec<sqlx::postgres::PgRow> {
+    let mut keyed: Vec<(Option<Vec<u64>>, sqlx::postgres::PgRow)> = rows
+        .into_iter()
+        .map(|r| (version::parse(&r.get::<String, _>("protocol_version")), r))
         .collect();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => version::compare(b, a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    keyed.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Advisory lock key held while a new host is checked against
+/// `MAX_TOTAL_NODES` ("hushcap" in ASCII).
+const MAX_TOTAL_NODES_LOCK: i64 = 0x0068_7573_6863_6170;
+
+/// Columns read for a public node object; see `node_json`.
+const NODE_COLUMNS: &str = "name, host, ip::text AS ip, api_base_url, protocol_version, features,
+    max_connections, weight, tags, tls_fingerprint, description, operator, software_version,
+    reported_software_version, self_reported_metrics, last_error, country_code, country_name, asn, org,
+    latitude, longitude, last_seen_at, last_latency_ms, status, email_verified, suspended, approved, is_onion, registered_at AS created_at, updated_at";
+
+/// Field names a node object can contain, for the `fields` projection.
+const NODE_FIELDS: &[&str] = &[
+    "name", "host", "ip", "api_base_url", "protocol_version", "features", "max_connections",
+    "weight", "tags", "tls_fingerprint", "description", "operator", "software_version",
+    "reported_software_version", "software_version_mismatch", "self_reported_metrics",
+    "last_error", "country_code", "country_name", "asn", "org", "latitude", "longitude",
+    "last_seen_at", "last_latency_ms", "status", "email_verified", "suspended", "approved", "is_onion", "created_at", "updated_at",
+];
 
-    Ok(Json(json!({ "nodes": nodes })))
+fn node_json(r: &sqlx::postgres::PgRow) -> serde_json::Value {
+    let software_version: Option<String> = r.get("software_version");
+    let reported: Option<String> = r.get("reported_software_version");
+    // Only a disagreement counts; a node that reports nothing is not flagged.
+    let mismatch = matches!((&software_version, &reported), (Some(a), Some(b)) if a != b);
+    json!({
+        "name": r.get::<String,_>("name"),
+        "host": r.get::<String,_>("host"),
+        "ip": r.get::<Option<String>,_>("ip"),
+        "api_base_url": r.get::<String,_>("api_base_url"),
+        "protocol_version": r.get::<String,_>("protocol_version"),
+        "features": r.get::<serde_json::Value,_>("features"),
+        "max_connections": r.get::<Option<i32>,_>("max_connections"),
+        "weight": r.get::<Option<i32>,_>("weight"),
+        "tags": r.get::<Vec<String>,_>("tags"),
+        "tls_fingerprint": r.get::<Option<String>,_>("tls_fingerprint"),
+        "description": r.get::<Option<String>,_>("description"),
+        "operator": r.get::<Option<serde_json::Value>,_>("operator"),
+        "software_version": software_version,
+        "reported_software_version": reported,
+        "software_version_mismatch": mismatch,
+        "self_reported_metrics": r.get::<Option<serde_json::Value>,_>("self_reported_metrics"),
+        "last_error": r.get::<Option<String>,_>("last_error"),
+        "country_code": r.get::<Option<String>,_>("country_code"),
+        "country_name": r.get::<Option<String>,_>("country_name"),
+        "asn": r.get::<Option<i64>,_>("asn"),
+        "org": r.get::<Option<String>,_>("org"),
+        "latitude": r.get::<Option<f64>,_>("latitude"),
+        "longitude": r.get::<Option<f64>,_>("longitude"),
+        "last_seen_at": r.get::<Option<DateTime<Utc>>,_>("last_seen_at"),
+        "last_latency_ms": r.get::<Option<i32>,_>("last_latency_ms"),
+        "status": r.get::<String,_>("status"),
+        "email_verified": r.get::<bool,_>("email_verified"),
+        "suspended": r.get::<bool,_>("suspended"),
+        "approved": r.get::<bool,_>("approved"),
+        "is_onion": r.get::<bool,_>("is_onion"),
+        "created_at": r.get::<DateTime<Utc>,_>("created_at"),
+        "updated_at": r.get::<DateTime<Utc>,_>("updated_at"),
+    })
 }
 
+/// Supports `If-None-Match`: the ETag changes with `updated_at` (advertised
+/// fields and status), `last_checked_at` (probe results) and `last_seen_at`
+/// (heartbeats), so an unchanged node costs pollers only a `304`.
+async fn get_node(
+    axum::extract::State(ReadPool(db)): axum::extract::State<ReadPool>,
+    axum::extract::Path(host): axum::extract::Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let row = sqlx::query(&format!("SELECT {NODE_COLUMNS}, last_checked_at FROM nodes WHERE host=$1"))
+        .bind(&host)
+        .fetch_optional(&db)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "node not found"))?;
+    let etag = http_cache::etag(&[
+        row.get("updated_at"),
+        row.get("last_checked_at"),
+        row.get("last_seen_at"),
+    ]);
+    if http_cache::not_modified(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok(([(header::ETAG, etag)], Json(node_json(&row))).into_response())
+}
+
+/// Finds a node by its identity key, which survives host changes. The key
+/// may be standard or URL-safe base64, padded or not.
+async fn get_node_by_pubkey(
+    axum::extract::State(ReadPool(db)): axum::extract::State<ReadPool>,
+    axum::extract::Path(pubkey_b64): axum::extract::Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let standard = pubkey_b64.replace('-', "+").replace('_', "/");
+    let pubkey = decode_b64_bounded("pubkey", &standard, MAX_PUBKEY_B64_LEN)?;
+    let row = sqlx::query(&format!("SELECT {NODE_COLUMNS} FROM nodes WHERE pubkey=$1"))
+        .bind(&pubkey)
+        .fetch_optional(&db)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "node not found"))?;
+    Ok(Json(node_json(&row)))
+}
+
+/// Keeps only `fields` of a node object.
+fn project(node: serde_json::Value, fields: &[String]) -> serde_json::Value {
+    match node {
+        serde_json::Value::Object(mut m) => {
+            m.retain(|k, _| fields.contains(k));
+            serde_json::Value::Object(m)
+        }
+        other => other,
+    }
+}
+
+/// Protocol versions this registry understands.
+const PROTOCOL_VERSIONS_SUPPORTED: &[&str] = &["1.0", "1.1"];
+
+async fn version_info() -> Json<serde_json::Value> {
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": option_env!("HUSHNET_GIT_SHA"),
+        "protocol_versions_supported": PROTOCOL_VERSIONS_SUPPORTED,
+    }))
+}
+
+/// Shows what the server signs for a register payload, to debug signature
+/// mismatches. Nothing is stored.
+async fn canon_preview(Json(req): Json<CanonReq>) -> Json<serde_json::Value> {
+    let canonical = canon::canonical_json_string(&req.payload, canon::array_order(&req.payload));
+    let message = canon::register_message(&req.payload, req.nonce.as_deref().unwrap_or(""));
+    let message_hex: String = message.iter().map(|b| format!("{b:02x}")).collect();
+    Json(json!({ "canonical": canonical, "message_hex": message_hex }))
+}
+
+fn json_body(body: axum::body::Bytes) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// Request headers the node list depends on: `Accept` picks the encoding and
+/// `X-Protocol-Version` filters it, so shared caches must key on both.
+const LIST_VARY: &str = "accept, x-protocol-version";
+
+/// Sends a node list body as is, or re-encoded as CBOR when the client's
+/// `Accept` lists `application/cbor`. The cache keeps JSON only.
+fn negotiated_body(body: axum::body::Bytes, headers: &HeaderMap) -> Result<Response, ApiError> {
+    let wants_cbor = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|range| range.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("application/cbor"));
+    let mut res = if wants_cbor {
+        let value: serde_json::Value = serde_json::from_slice(&body).map_err(internal)?;
+        let mut out = Vec::new();
+        ciborium::into_writer(&value, &mut out).map_err(internal)?;
+        ([(header::CONTENT_TYPE, "application/cbor")], out).into_response()
+    } else {
+        json_body(body)
+    };
+    res.headers_mut().insert(header::VARY, header::HeaderValue::from_static(LIST_VARY));
+    Ok(res)
+}
 
 fn gen_nonce() -> String {
     let mut b = [0u8; 24];
@@ -255,129 +1115,230 @@ fn gen_nonce() -> String {
     URL_SAFE_NO_PAD.encode(b)
 }
 
-fn bad(s: &'static str) -> (StatusCode, String) {
-    (StatusCode::BAD_REQUEST, format!("missing/invalid {}", s))
+fn bad(s: &'static str) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, format!("missing/invalid {}", s))
 }
-fn badreq<E: std::fmt::Display>(e: E) -> (StatusCode, String) {
-    (StatusCode::BAD_REQUEST, e.to_string())
+
+fn corrupt_node_key(host: &str, reason: &str) -> ApiError {
+    error!("node {host}: stored pubkey is not a valid key ({reason})");
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "corrupt node key")
 }
-fn internal<E: std::fmt::Display>(e: E) -> (StatusCode, String) {
+
+fn internal<E: std::fmt::Display>(e: E) -> ApiError {
     error!("{e}");
-    (StatusCode::INTERNAL_SERVER_ERROR, "internal".into())
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal")
 }
 
-async fn resolve_ip(host: &str) -> anyhow::Result<String> {
-    let addr = format!("{host}:0");
-    let ip = addr
-        .to_socket_addrs()?
-        .next()
-        .ok_or(anyhow::anyhow!("no dns"))?
-        .ip();
-    // Remove the /32 or /128 suffix if present
-
-    Ok(ip.to_string())
+/// Permissive CORS with preflight results cacheable for `CORS_MAX_AGE_SECS`.
+fn cors_layer() -> CorsLayer {
+    let max_age: u64 = std::env::var("CORS_MAX_AGE_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(600);
+    CorsLayer::permissive().max_age(StdDuration::from_secs(max_age))
 }
 
+/// Builds the single outbound HTTP client shared by every background task, so
+/// keep-alive connections survive across health sweeps.
+fn http_client() -> anyhow::Result<reqwest::Client> {
+    Ok(http_client_builder().build()?)
+}
 
-async fn health_worker(db: PgPool) {
-    let client = reqwest::Client::new();
-    let timeout_ms: u64 = std::env::var("HEALTH_TIMEOUT_MS")
+/// Pool settings shared by every outbound client.
+fn http_client_builder() -> reqwest::ClientBuilder {
+    let max_idle: usize = std::env::var("HTTP_POOL_MAX_IDLE_PER_HOST")
         .ok()
         .and_then(|s| s.parse().ok())
-        .unwrap_or(3000);
+        .unwrap_or(4);
+    let idle_secs: u64 = std::env::var("HTTP_POOL_IDLE_TIMEOUT_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(90);
 
-    loop {
-        if let Err(e) = tick_health(&db, &client, timeout_ms).await {
-            error!("health tick error: {e}");
+    reqwest::Client::builder()
+        .tls_info(true)
+        .pool_max_idle_per_host(max_idle)
+        .pool_idle_timeout(StdDuration::from_secs(idle_secs))
+        .tcp_keepalive(StdDuration::from_secs(60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{ConnectInfo, State};
+    use base64::engine::general_purpose::STANDARD as B64;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    // Needs a database with sql_models/seed.sql applied, named by
+    // TEST_DATABASE_URL. Skipped when that is unset.
+    async fn test_db() -> Option<PgPool> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(PgPool::connect(&url).await.expect("TEST_DATABASE_URL not reachable"))
+    }
+
+    fn test_state(db: PgPool, cfg: Config) -> AppState {
+        let list_cache = Arc::new(ListCache::from_env());
+        let counts = Arc::new(StatusCounts::new());
+        let health = HealthWorker::new(db.clone(), http_client().unwrap(), list_cache.clone(), counts.clone());
+        AppState {
+            read_db: ReadPool(db.clone()),
+            db,
+            list_cache,
+            admin: Arc::new(AdminAuth::from_env()),
+            maintenance: Arc::new(Maintenance::from_env()),
+            replay: Arc::new(ReplayCache::new(16)),
+            cfg: Arc::new(cfg),
+            dns: Arc::new(Dns::from_env().unwrap()),
+            counts,
+            mailer: Arc::new(Mailer::from_env().unwrap()),
+            health: Arc::new(health.unwrap()),
+            rate_limiter: Arc::new(RateLimiter::from_env()),
+            write_limiter: Arc::new(WriteLimiter::from_env()),
+            snapshot_signer: Arc::new(snapshot::SnapshotSigner::from_env().unwrap()),
         }
-        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
     }
-}
 
-async fn tick_health(
-    db: &PgPool,
-    client: &reqwest::Client,
-    timeout_ms: u64,
-) -> anyhow::Result<()> {
-    static DB_PATH: &str = "data/GeoLite2-City.mmdb";
-    let reader = Arc::new(Reader::open_readfile(DB_PATH)?);
-    let nodes = sqlx::query("SELECT host, api_base_url, ip::text FROM nodes")
-        .fetch_all(db)
-        .await?;
-
-    for row in nodes {
-        let host: String = row.get("host");
-        let api: String = row.get("api_base_url");
-        let ip: Option<String> = row.get("ip");
-        println!("Checking health for node {host} at {api}");
-        // Measure latency
-        let start = std::time::Instant::now();
-        let res = client
-            .get(format!("{api}/health"))
-            .timeout(StdDuration::from_millis(timeout_ms))
-            .send()
-            .await;
-
-        let (status, latency) = match res {
-            Ok(r) if r.status().is_success() => ("online", Some(start.elapsed().as_millis() as i32)),
-            _ => ("offline", None),
-        };
+    /// A random, valid v3 onion host; onion hosts need no DNS.
+    fn onion_host() -> String {
+        let label: String = (0..56)
+            .map(|_| b"abcdefghijklmnopqrstuvwxyz234567"[rand::random::<usize>() % 32] as char)
+            .collect();
+        format!("{label}.onion")
+    }
 
-        // GeoIP if online and geo info missing
-        let (cc, cn) = {
-            let mut country_code: Option<String> = None;
-            let mut country_name: Option<String> = None;
+    /// A challenge row and a register request for a fresh onion host.
+    async fn signed_register(db: &PgPool) -> (String, String, serde_json::Value) {
+        let key = SigningKey::from_bytes(&rand::random());
+        let host = onion_host();
+        let pubkey_b64 = B64.encode(key.verifying_key().to_bytes());
+        let (nonce, req) = sign_for(db, &key, &host, &pubkey_b64).await;
+        (host, nonce, req)
+    }
 
-            if let Some(mut ip_str) = ip.as_deref() {
-                // Remove the /32 or /128 suffix if present
-                if let Some(pos) = ip_str.find('/') {
-                    ip_str = &ip_str[..pos];
-                }
-                println!("Looking up GeoIP for IP {ip_str}");
-                let ip_addr: std::net::IpAddr = match ip_str.parse() {
-                    Ok(addr) => addr,
-                    Err(_) => {
-                        println!("Could not parse IP address {ip_str} for GeoIP lookup");
-                        // skip GeoIP lookup for this node and continue with next node
-                        continue;
-                    }
-                };
-
-                if let Ok(city) = reader.lookup::<City>(ip_addr) {
-                    country_code = city.country
-                        .as_ref()
-                        .and_then(|c| c.iso_code)
-                        .map(|s| s.to_string());
-                    country_name = city.country
-                        .as_ref()
-                        .and_then(|c| c.names.as_ref())
-                        .and_then(|m| m.get("en"))
-                        .map(|s| s.to_string());
-                }
-            }
+    /// A fresh challenge for `pubkey_b64` and `host`'s register request
+    /// signed with `key` over it.
+    async fn sign_for(db: &PgPool, key: &SigningKey, host: &str, pubkey_b64: &str) -> (String, serde_json::Value) {
+        let nonce = format!("test-{}", URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>()));
+        sqlx::query("INSERT INTO challenges (nonce, pubkey_b64, expires_at) VALUES ($1, $2, now() + interval '5 minutes')")
+            .bind(&nonce)
+            .bind(pubkey_b64)
+            .execute(db)
+            .await
+            .unwrap();
+        let payload = json!({
+            "name": "register test",
+            "host": host,
+            "api_base_url": format!("http://{host}"),
+            "protocol_version": "1.0",
+            "pubkey": pubkey_b64,
+        });
+        let signature = key.sign(&canon::register_message(&payload, &nonce));
+        let req = json!({
+            "payload": payload,
+            "nonce": nonce,
+            "signature_b64": B64.encode(signature.to_bytes()),
+            "pubkey_b64": pubkey_b64,
+        });
+        (nonce, req)
+    }
 
-            (country_code, country_name)
-        };
+    async fn call_register(state: &AppState, req: &serde_json::Value) -> Result<Json<serde_json::Value>, ApiError> {
+        let req = serde_json::from_value(req.clone()).unwrap();
+        let peer = ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0)));
+        register(State(state.clone()), peer, HeaderMap::new(), Json(req)).await
+    }
 
+    async fn nonce_exists(db: &PgPool, nonce: &str) -> bool {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM challenges WHERE nonce = $1)")
+            .bind(nonce)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
 
-        sqlx::query(
-            r#"
-            UPDATE nodes
-            SET status=$1,
-                last_latency_ms=$2,
-                last_seen_at = CASE WHEN $1='online' THEN now() ELSE last_seen_at END,
-                country_code = COALESCE($3, country_code),
-                country_name = COALESCE($4, country_name)
-            WHERE host=$5
-            "#,
-        )
-        .bind(status)
-        .bind(latency)
-        .bind(cc)
-        .bind(cn)
-        .bind(&host)
-        .execute(db)
-        .await?;
+    #[tokio::test]
+    async fn register_failing_mid_transaction_rolls_back_and_can_be_retried() {
+        let Some(db) = test_db().await else { return };
+
+        // Makes sure the registry holds at least one node for the cap below.
+        let (first_host, _, first) = signed_register(&db).await;
+        let open = test_state(db.clone(), Config::from_env().unwrap());
+        let Json(res) = call_register(&open, &first).await.unwrap();
+        assert_eq!(res["ok"], true);
+
+        // A cap the registry is already at fails a new host inside the
+        // transaction, after its nonce has been claimed.
+        let (host, nonce, req) = signed_register(&db).await;
+        let mut cfg = Config::from_env().unwrap();
+        cfg.max_total_nodes = sqlx::query_scalar("SELECT count(*) FROM nodes").fetch_one(&db).await.unwrap();
+        let mut state = test_state(db.clone(), cfg);
+        let err = call_register(&state, &req).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::INSUFFICIENT_STORAGE);
+        assert!(nonce_exists(&db, &nonce).await, "nonce claim was not rolled back");
+
+        // The identical signed request goes through once there is room, on
+        // the same replay cache.
+        let mut cfg = Config::from_env().unwrap();
+        cfg.max_total_nodes = 0;
+        state.cfg = Arc::new(cfg);
+        let Json(res) = call_register(&state, &req).await.unwrap();
+        assert_eq!(res["ok"], true);
+        assert!(!nonce_exists(&db, &nonce).await);
+        let err = call_register(&state, &req).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+
+        sqlx::query("DELETE FROM nodes WHERE host = ANY($1)")
+            .bind([first_host, host])
+            .execute(&db)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn reregistering_with_differently_padded_key_is_the_same_key() {
+        let Some(db) = test_db().await else { return };
+        let state = test_state(db.clone(), Config::from_env().unwrap());
+        let key = SigningKey::from_bytes(&rand::random());
+        let host = onion_host();
+
+        let padded = B64.encode(key.verifying_key().to_bytes());
+        let unpadded = padded.trim_end_matches('=').to_owned();
+        assert_ne!(padded, unpadded);
+        for pubkey_b64 in [&padded, &unpadded] {
+            let (_, req) = sign_for(&db, &key, &host, pubkey_b64).await;
+            let Json(res) = call_register(&state, &req).await.unwrap();
+            assert_eq!(res["ok"], true);
+        }
+
+        sqlx::query("DELETE FROM nodes WHERE host = $1").bind(&host).execute(&db).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn payload_naming_another_key_is_rejected() {
+        let Some(db) = test_db().await else { return };
+        let state = test_state(db.clone(), Config::from_env().unwrap());
+        let key = SigningKey::from_bytes(&rand::random());
+        let other = SigningKey::from_bytes(&rand::random());
+        let (_, mut req) = sign_for(&db, &key, &onion_host(), &B64.encode(key.verifying_key().to_bytes())).await;
+
+        // Correctly signed by `key`, but the payload commits to `other`.
+        req["payload"]["pubkey"] = json!(B64.encode(other.verifying_key().to_bytes()));
+        let message = canon::register_message(&req["payload"], req["nonce"].as_str().unwrap());
+        req["signature_b64"] = json!(B64.encode(key.sign(&message).to_bytes()));
+
+        let err = call_register(&state, &req).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert_eq!(err.message, "pubkey not bound in payload");
+    }
+
+    #[test]
+    fn node_list_varies_on_accept_and_protocol_header() {
+        for accept in ["application/json", "application/cbor"] {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, accept.parse().unwrap());
+            headers.insert("x-protocol-version", "1.1".parse().unwrap());
+            let res = negotiated_body(axum::body::Bytes::from_static(b"[]"), &headers).unwrap();
+            assert_eq!(res.headers()[header::VARY], "accept, x-protocol-version");
+        }
     }
-    Ok(())
 }
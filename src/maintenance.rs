@@ -0,0 +1,57 @@
+// src/maintenance.rs
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::error::ApiError;
+
+/// Process-wide maintenance flag. While enabled, write endpoints answer
+/// `503 maintenance` and reads keep being served.
+pub struct Maintenance {
+    enabled: AtomicBool,
+    retry_after_secs: u64,
+}
+
+impl Maintenance {
+    pub fn from_env() -> Self {
+        let enabled = crate::config::env_flag("MAINTENANCE_MODE", false);
+        let retry_after_secs = std::env::var("MAINTENANCE_RETRY_AFTER_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(300);
+        if enabled {
+            warn!("starting in maintenance mode");
+        }
+        Self { enabled: AtomicBool::new(enabled), retry_after_secs }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        let was = self.enabled.swap(enabled, Ordering::Relaxed);
+        match (was, enabled) {
+            (false, true) => warn!("entering maintenance mode"),
+            (true, false) => warn!("leaving maintenance mode"),
+            _ => {}
+        }
+    }
+}
+
+/// Route layer for write endpoints.
+pub async fn guard(
+    State(maintenance): State<Arc<Maintenance>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if maintenance.is_enabled() {
+        return ApiError::unavailable("maintenance", maintenance.retry_after_secs).into_response();
+    }
+    next.run(req).await
+}
@@ -1,2 +1,34 @@
+pub mod admin;
+pub mod audit;
+pub mod backpressure;
+pub mod cache;
 pub mod canon;
-pub mod types;
\ No newline at end of file
+pub mod cli;
+pub mod client_ip;
+pub mod config;
+pub mod content_type;
+pub mod crypto;
+pub mod dns;
+pub mod email;
+pub mod error;
+pub mod feed;
+pub mod gc;
+pub mod geojson;
+pub mod health;
+pub mod http_cache;
+pub mod idempotency;
+pub mod list_query;
+pub mod maintenance;
+pub mod pow;
+pub mod ratelimit;
+pub mod readiness;
+pub mod replay;
+pub mod schema;
+pub mod server;
+pub mod slowlog;
+pub mod snapshot;
+pub mod stats;
+pub mod types;
+pub mod uptime;
+pub mod validate;
+pub mod version;
\ No newline at end of file
@@ -0,0 +1,36 @@
+// src/pow.rs
+use sha2::{Digest, Sha256};
+
+/// Highest accepted `POW_DIFFICULTY`; each extra bit doubles the expected work.
+pub const MAX_DIFFICULTY: u32 = 32;
+/// Longest accepted `pow_nonce`.
+pub const MAX_NONCE_LEN: usize = 64;
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for b in hash {
+        bits += b.leading_zeros();
+        if *b != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Whether SHA-256(`challenge` || `pow_nonce`) starts with at least
+/// `difficulty` zero bits.
+pub fn verify(challenge: &str, pow_nonce: &str, difficulty: u32) -> bool {
+    let hash = Sha256::new()
+        .chain_update(challenge.as_bytes())
+        .chain_update(pow_nonce.as_bytes())
+        .finalize();
+    leading_zero_bits(&hash) >= difficulty
+}
+
+/// Finds a `pow_nonce` for `challenge` by counting up from 0.
+pub fn solve(challenge: &str, difficulty: u32) -> String {
+    (0u64..)
+        .map(|n| n.to_string())
+        .find(|n| verify(challenge, n, difficulty))
+        .expect("a solution exists below u64::MAX")
+}
@@ -0,0 +1,113 @@
+// src/ratelimit.rs
+use axum::{
+    extract::{connect_info::ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::error::ApiError;
+
+/// Buckets kept before expired ones are swept.
+const SWEEP_AT: usize = 10_000;
+
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// Result of counting one request against its client's budget.
+struct Budget {
+    allowed: bool,
+    remaining: u32,
+    reset_secs: u64,
+}
+
+/// Fixed-window request budget per client IP for the challenge and register
+/// endpoints (`RATE_LIMIT_PER_MINUTE`, 0 disables).
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    buckets: Mutex<HashMap<IpAddr, Window>>,
+}
+
+impl RateLimiter {
+    pub fn from_env() -> Self {
+        let limit = std::env::var("RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        Self { limit, window: Duration::from_secs(60), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    fn hit(&self, ip: IpAddr) -> Budget {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= SWEEP_AT {
+            buckets.retain(|_, w| now.duration_since(w.start) < self.window);
+        }
+        let w = buckets.entry(ip).or_insert(Window { start: now, count: 0 });
+        if now.duration_since(w.start) >= self.window {
+            *w = Window { start: now, count: 0 };
+        }
+        let allowed = w.count < self.limit;
+        if allowed {
+            w.count += 1;
+        }
+        let reset = self.window.saturating_sub(now.duration_since(w.start));
+        Budget {
+            allowed,
+            remaining: self.limit - w.count,
+            reset_secs: reset.as_secs() + u64::from(reset.subsec_nanos() > 0),
+        }
+    }
+}
+
+/// Counts one request from `ip` outside the route layer, for batch entries
+/// that each spend the budget like a single request. `Err` carries the
+/// seconds until the window resets.
+pub fn take(limiter: &RateLimiter, ip: IpAddr) -> Result<(), u64> {
+    if limiter.limit == 0 {
+        return Ok(());
+    }
+    let budget = limiter.hit(ip);
+    if budget.allowed {
+        Ok(())
+    } else {
+        Err(budget.reset_secs)
+    }
+}
+
+fn set_headers(headers: &mut HeaderMap, limit: u32, budget: &Budget) {
+    headers.insert("x-ratelimit-limit", HeaderValue::from(limit));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from(budget.remaining));
+    headers.insert("x-ratelimit-reset", HeaderValue::from(budget.reset_secs));
+}
+
+/// Route layer counting requests per client IP. Every response carries the
+/// `X-RateLimit-*` headers; over budget it answers `429` with `Retry-After`.
+pub async fn layer(
+    State(limiter): State<Arc<RateLimiter>>,
+    State(cfg): State<Arc<Config>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if limiter.limit == 0 {
+        return next.run(req).await;
+    }
+    let ip = cfg.proxies.client_ip(req.headers(), peer);
+    let budget = limiter.hit(ip);
+    let mut res = if budget.allowed {
+        next.run(req).await
+    } else {
+        ApiError::too_many_requests("rate limit exceeded", budget.reset_secs).into_response()
+    };
+    set_headers(res.headers_mut(), limiter.limit, &budget);
+    res
+}
@@ -0,0 +1,55 @@
+// src/readiness.rs
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::error::ApiError;
+use crate::ReadPool;
+
+/// Bound on each dependency check, at startup and in `/readyz`.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Acquires a connection from `pool` and runs `SELECT 1` on it.
+async fn ping(pool: &PgPool) -> anyhow::Result<()> {
+    tokio::time::timeout(CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(pool))
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s", CHECK_TIMEOUT.as_secs()))??;
+    Ok(())
+}
+
+/// Runs before the listener is bound, so the first requests do not pay for
+/// cold connections. Unreachable databases abort startup. With `WARMUP_URL`
+/// set, the outbound client also fetches it once to warm DNS and TLS; a
+/// failure there is only logged.
+pub async fn warmup(db: &PgPool, read_db: &PgPool, client: &reqwest::Client) -> anyhow::Result<()> {
+    let start = Instant::now();
+    ping(db).await.map_err(|e| anyhow::anyhow!("database not reachable: {e}"))?;
+    ping(read_db).await.map_err(|e| anyhow::anyhow!("read database not reachable: {e}"))?;
+    if let Ok(url) = std::env::var("WARMUP_URL") {
+        if !url.trim().is_empty() {
+            match client.get(url.trim()).timeout(CHECK_TIMEOUT).send().await {
+                Ok(res) => info!("warmup: {url} answered {}", res.status()),
+                Err(e) => warn!("warmup: could not fetch {url}: {e}"),
+            }
+        }
+    }
+    info!("warmup done in {} ms", start.elapsed().as_millis());
+    Ok(())
+}
+
+/// Readiness probe for orchestrators: `200` while both database pools answer,
+/// `503` otherwise. Liveness needs no endpoint of its own; any route will do.
+pub async fn readyz(
+    State(db): State<PgPool>,
+    State(ReadPool(read_db)): State<ReadPool>,
+) -> Result<Json<Value>, ApiError> {
+    for (name, pool) in [("database", &db), ("read database", &read_db)] {
+        if let Err(e) = ping(pool).await {
+            warn!("readyz: {name} not reachable: {e}");
+            return Err(ApiError::unavailable(format!("{name} not reachable"), 5));
+        }
+    }
+    Ok(Json(json!({ "status": "ready" })))
+}
@@ -0,0 +1,119 @@
+// src/replay.rs
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// Bounded set of recently accepted signatures, kept per endpoint. Oldest
+/// entries are evicted first once `capacity` is reached.
+pub struct ReplayCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    seen: HashSet<(&'static str, Vec<u8>)>,
+    order: VecDeque<(&'static str, Vec<u8>)>,
+}
+
+impl ReplayCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, inner: Mutex::new(Inner::default()) }
+    }
+
+    pub fn from_env() -> Self {
+        let capacity = std::env::var("REPLAY_CACHE_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(10_000);
+        Self::new(capacity)
+    }
+
+    /// Claims `sig` for `endpoint`. Returns `None` if it was already seen.
+    ///
+    /// The claim is what keeps two concurrent copies of a request from both
+    /// proceeding. It is released when dropped, so a request that fails
+    /// later can be retried with the same signature; call
+    /// [`ReplayClaim::keep`] once the request has taken effect.
+    pub fn claim(&self, endpoint: &'static str, sig: &[u8]) -> Option<ReplayClaim<'_>> {
+        if self.capacity == 0 {
+            return Some(ReplayClaim { cache: self, key: None });
+        }
+        let key = (endpoint, sig.to_vec());
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.seen.insert(key.clone()) {
+            return None;
+        }
+        inner.order.push_back(key.clone());
+        while inner.order.len() > self.capacity {
+            if let Some(old) = inner.order.pop_front() {
+                inner.seen.remove(&old);
+            }
+        }
+        Some(ReplayClaim { cache: self, key: Some(key) })
+    }
+
+    fn release(&self, key: &(&'static str, Vec<u8>)) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.seen.remove(key) {
+            // Claims are released soon after being taken, so near the back.
+            if let Some(i) = inner.order.iter().rposition(|k| k == key) {
+                inner.order.remove(i);
+            }
+        }
+    }
+}
+
+/// A signature held in the [`ReplayCache`] by a request in flight.
+#[must_use]
+pub struct ReplayClaim<'a> {
+    cache: &'a ReplayCache,
+    key: Option<(&'static str, Vec<u8>)>,
+}
+
+impl ReplayClaim<'_> {
+    /// Keeps the signature recorded for good.
+    pub fn keep(mut self) {
+        self.key = None;
+    }
+}
+
+impl Drop for ReplayClaim<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.release(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplayCache;
+
+    #[test]
+    fn kept_claim_rejects_the_same_signature() {
+        let cache = ReplayCache::new(8);
+        cache.claim("register", b"sig").unwrap().keep();
+        assert!(cache.claim("register", b"sig").is_none());
+        assert!(cache.claim("heartbeat", b"sig").is_some());
+    }
+
+    #[test]
+    fn dropped_claim_allows_a_retry() {
+        let cache = ReplayCache::new(8);
+        let claim = cache.claim("register", b"sig").unwrap();
+        assert!(cache.claim("register", b"sig").is_none());
+        drop(claim);
+        cache.claim("register", b"sig").unwrap().keep();
+        assert!(cache.claim("register", b"sig").is_none());
+    }
+
+    #[test]
+    fn oldest_signature_is_evicted() {
+        let cache = ReplayCache::new(2);
+        for sig in [b"a", b"b", b"c"] {
+            cache.claim("register", sig).unwrap().keep();
+        }
+        assert!(cache.claim("register", b"a").is_some());
+        assert!(cache.claim("register", b"c").is_none());
+    }
+}
@@ -0,0 +1,61 @@
+// src/schema.rs
+use sqlx::PgPool;
+use std::collections::HashSet;
+
+/// Columns the code reads or writes, per table. Keep in step with
+/// `sql_models/seed.sql` when adding a column.
+const EXPECTED: &[(&str, &[&str])] = &[
+    (
+        "nodes",
+        &[
+            "name", "host", "ip", "api_base_url", "pubkey", "protocol_version", "features",
+            "contact_email", "registered_at", "country_code", "country_name", "last_seen_at",
+            "last_latency_ms", "status", "last_checked_at", "next_check_at", "email_verified",
+            "consecutive_successes", "consecutive_failures", "updated_at", "max_connections",
+            "weight", "suspended", "tags", "alg", "tls_fingerprint", "last_error",
+            "approved", "is_onion", "description", "latitude", "longitude", "last_heartbeat_at",
+            "software_version", "reported_software_version", "geo_overridden", "asn", "org",
+            "self_reported_metrics", "operator",
+        ],
+    ),
+    ("challenges", &["nonce", "pubkey_b64", "alg", "expires_at", "pow_difficulty"]),
+    (
+        "idempotency_keys",
+        &["pubkey_b64", "key", "status", "content_type", "body", "created_at"],
+    ),
+    ("email_verifications", &["token", "host", "email", "expires_at"]),
+    ("health_checks", &["id", "host", "checked_at", "status", "latency_ms"]),
+    ("status_changes", &["id", "host", "changed_at", "from_status", "to_status"]),
+    ("audit_log", &["id", "created_at", "action", "host", "details"]),
+];
+
+/// Fails with the list of missing columns when the database schema is older
+/// than this build, so a stale schema stops startup instead of surfacing as
+/// 500s on whichever query first touches a missing column.
+pub async fn check(db: &PgPool) -> anyhow::Result<()> {
+    let tables: Vec<&str> = EXPECTED.iter().map(|(t, _)| *t).collect();
+    let present: HashSet<(String, String)> = sqlx::query_as(
+        "SELECT table_name::text, column_name::text
+         FROM information_schema.columns
+         WHERE table_schema = current_schema() AND table_name = ANY($1)",
+    )
+    .bind(&tables)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .collect();
+
+    let missing: Vec<String> = EXPECTED
+        .iter()
+        .flat_map(|(table, cols)| cols.iter().map(move |col| (*table, *col)))
+        .filter(|(table, col)| !present.contains(&(table.to_string(), col.to_string())))
+        .map(|(table, col)| format!("{table}.{col}"))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "database schema is incompatible with this version, missing columns: {}; apply sql_models/seed.sql",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+// src/server.rs
+use axum::{
+    extract::{connect_info::ConnectInfo, Request},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use socket2::{SockRef, TcpKeepalive};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+/// HTTP server settings, read once at startup.
+pub struct ServerOptions {
+    /// Accept HTTP/2 (prior-knowledge h2c) next to HTTP/1.1. TLS, and with it
+    /// ALPN-negotiated h2, is expected to terminate at a reverse proxy.
+    pub http2: bool,
+    /// How long in-flight requests may take to finish after a shutdown signal.
+    pub shutdown_grace: Duration,
+    /// Pending-connection queue length passed to `listen(2)`.
+    pub backlog: u32,
+    /// Idle time before TCP keepalive probes (and HTTP/2 pings) are sent on a
+    /// connection. `None` turns HTTP/1.1 keep-alive off.
+    pub keepalive: Option<Duration>,
+    /// Cap on simultaneously open connections; further clients wait in the
+    /// backlog. `None` is unlimited.
+    pub max_connections: Option<usize>,
+}
+
+impl ServerOptions {
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: u64| -> u64 {
+            std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+        };
+        let keepalive_secs = var("HTTP_KEEPALIVE_SECS", 60);
+        let max_connections = var("HTTP_MAX_CONNECTIONS", 0) as usize;
+        Self {
+            http2: crate::config::env_flag("HTTP2_ENABLED", true),
+            shutdown_grace: Duration::from_secs(var("SHUTDOWN_GRACE_SECS", 10)),
+            backlog: var("TCP_BACKLOG", 1024).clamp(1, u32::MAX as u64) as u32,
+            keepalive: (keepalive_secs > 0).then(|| Duration::from_secs(keepalive_secs)),
+            max_connections: (max_connections > 0).then_some(max_connections),
+        }
+    }
+}
+
+/// Binds `addr` with the configured accept backlog.
+pub fn bind(addr: SocketAddr, opts: &ServerOptions) -> std::io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(opts.backlog)
+}
+
+/// Accept loop serving `app` until SIGINT/SIGTERM, then drains open
+/// connections for at most `shutdown_grace`.
+pub async fn serve(listener: TcpListener, app: Router, opts: ServerOptions) -> anyhow::Result<()> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder.http1().keep_alive(opts.keepalive.is_some());
+    if let Some(every) = opts.keepalive {
+        builder.http2().timer(hyper_util::rt::TokioTimer::new()).keep_alive_interval(every);
+    }
+    if !opts.http2 {
+        builder = builder.http1_only();
+    }
+    let limit = opts.max_connections.map(|n| Arc::new(Semaphore::new(n)));
+    info!(
+        "server settings: http2={} backlog={} keepalive={} max_connections={}",
+        opts.http2,
+        opts.backlog,
+        opts.keepalive.map_or("off".to_owned(), |d| format!("{}s", d.as_secs())),
+        opts.max_connections.map_or("unlimited".to_owned(), |n| n.to_string()),
+    );
+    let graceful = GracefulShutdown::new();
+    let mut shutdown = std::pin::pin!(shutdown_signal());
+
+    loop {
+        // Wait for a free slot before accepting, so excess clients queue in
+        // the kernel backlog instead of being accepted and starved.
+        let permit = match &limit {
+            Some(sem) => tokio::select! {
+                p = sem.clone().acquire_owned() => Some(p.expect("semaphore is never closed")),
+                _ = &mut shutdown => break,
+            },
+            None => None,
+        };
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("accept error: {e}");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        if let Some(idle) = opts.keepalive {
+            if let Err(e) = SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle)) {
+                tracing::debug!("could not enable tcp keepalive: {e}");
+            }
+        }
+
+        let app = app.clone();
+        let svc = TowerToHyperService::new(app.map_request(move |req: Request<Incoming>| {
+            let mut req = req.map(axum::body::Body::new);
+            req.extensions_mut().insert(ConnectInfo(peer));
+            req
+        }));
+        let conn = builder.serve_connection_with_upgrades(TokioIo::new(stream), svc).into_owned();
+        let conn = graceful.watch(conn);
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                tracing::debug!("connection closed with error: {e}");
+            }
+            drop(permit);
+        });
+    }
+
+    info!("shutting down, draining connections");
+    drop(listener);
+    if tokio::time::timeout(opts.shutdown_grace, graceful.shutdown()).await.is_err() {
+        warn!("grace period elapsed with connections still open");
+    }
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        if let Ok(mut s) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            s.recv().await;
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,51 @@
+// src/slowlog.rs
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Requests taking longer than this are logged (`SLOW_REQUEST_MS`, default
+/// 1000, 0 disables).
+pub struct SlowRequests {
+    threshold: Option<Duration>,
+}
+
+impl SlowRequests {
+    pub fn from_env() -> Self {
+        let ms: u64 = std::env::var("SLOW_REQUEST_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1000);
+        Self { threshold: (ms > 0).then(|| Duration::from_millis(ms)) }
+    }
+}
+
+/// Outer layer timing the whole request, including the timeout layer.
+pub async fn layer(State(slow): State<Arc<SlowRequests>>, req: Request, next: Next) -> Response {
+    let Some(threshold) = slow.threshold else {
+        return next.run(req).await;
+    };
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_owned();
+    let start = Instant::now();
+    let res = next.run(req).await;
+    let elapsed = start.elapsed();
+    if elapsed > threshold {
+        warn!(
+            "slow request {method} {path} took {}ms (status {}, request id {request_id})",
+            elapsed.as_millis(),
+            res.status().as_u16()
+        );
+    }
+    res
+}
@@ -0,0 +1,76 @@
+// src/snapshot.rs
+use axum::{extract::State, http::StatusCode, Json};
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use chrono::{SecondsFormat, Utc};
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::{json, Value};
+use sqlx::Row;
+use std::sync::Arc;
+
+use crate::canon::{self, ArrayOrder};
+use crate::error::ApiError;
+use crate::ReadPool;
+
+/// The registry's own Ed25519 key (`REGISTRY_SIGNING_KEY`, base64 of the
+/// 32-byte secret). Without it snapshots are not served.
+pub struct SnapshotSigner {
+    key: Option<SigningKey>,
+}
+
+impl SnapshotSigner {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let key = match std::env::var("REGISTRY_SIGNING_KEY") {
+            Ok(raw) if !raw.trim().is_empty() => {
+                let bytes = crate::crypto::decode_b64(&raw)
+                    .map_err(|e| anyhow::anyhow!("invalid REGISTRY_SIGNING_KEY: {e}"))?;
+                let seed: [u8; 32] = bytes
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("REGISTRY_SIGNING_KEY must be 32 bytes"))?;
+                Some(SigningKey::from_bytes(&seed))
+            }
+            _ => None,
+        };
+        Ok(Self { key })
+    }
+}
+
+/// The public node list, signed by the registry so it can be redistributed
+/// and verified offline. The signature covers the canonical JSON of
+/// `snapshot`, arrays in order.
+pub async fn node_snapshot(
+    State(ReadPool(db)): State<ReadPool>,
+    State(signer): State<Arc<SnapshotSigner>>,
+) -> Result<Json<Value>, ApiError> {
+    let Some(key) = &signer.key else {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "snapshots are not enabled"));
+    };
+    let rows = sqlx::query(&format!(
+        "SELECT {}, pubkey FROM nodes WHERE NOT suspended AND approved ORDER BY host",
+        crate::NODE_COLUMNS
+    ))
+    .fetch_all(&db)
+    .await
+    .map_err(crate::internal)?;
+
+    let nodes: Vec<Value> = rows
+        .iter()
+        .map(|r| {
+            let mut node = crate::node_json(r);
+            node["pubkey"] = json!(B64.encode(r.get::<Vec<u8>, _>("pubkey")));
+            node
+        })
+        .collect();
+    let snapshot = json!({
+        "generated_at": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        "nodes": nodes,
+    });
+    let message = canon::canonical_json_string(&snapshot, ArrayOrder::Preserve);
+    let signature = key.sign(message.as_bytes());
+
+    Ok(Json(json!({
+        "snapshot": snapshot,
+        "alg": crate::crypto::DEFAULT_ALG,
+        "pubkey_b64": B64.encode(key.verifying_key().to_bytes()),
+        "signature_b64": B64.encode(signature.to_bytes()),
+    })))
+}
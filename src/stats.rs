@@ -0,0 +1,197 @@
+// src/stats.rs
+use arc_swap::ArcSwap;
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::error;
+
+use crate::config::Config;
+use crate::ReadPool;
+
+#[derive(Serialize, Default)]
+pub struct Counts {
+    pub total: i64,
+    pub by_status: BTreeMap<String, i64>,
+    pub computed_at: Option<DateTime<Utc>>,
+}
+
+/// Node counts per status, recomputed in the background so that scrapes
+/// never hit the database.
+pub struct StatusCounts {
+    snapshot: ArcSwap<Counts>,
+    dirty: Notify,
+}
+
+impl StatusCounts {
+    pub fn new() -> Self {
+        Self { snapshot: ArcSwap::from_pointee(Counts::default()), dirty: Notify::new() }
+    }
+
+    pub fn get(&self) -> Arc<Counts> {
+        self.snapshot.load_full()
+    }
+
+    /// Asks the refresh task to recompute now instead of at the next interval.
+    pub fn poke(&self) {
+        self.dirty.notify_one();
+    }
+}
+
+pub async fn refresh_worker(db: PgPool, counts: Arc<StatusCounts>) {
+    let every_secs: u64 = std::env::var("STATUS_COUNTS_REFRESH_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(15);
+
+    loop {
+        match compute(&db).await {
+            Ok(c) => counts.snapshot.store(Arc::new(c)),
+            Err(e) => error!("status counts refresh error: {e}"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(every_secs)) => {}
+            _ = counts.dirty.notified() => {}
+        }
+    }
+}
+
+async fn compute(db: &PgPool) -> anyhow::Result<Counts> {
+    let rows = sqlx::query("SELECT status, count(*) AS n FROM nodes GROUP BY status")
+        .fetch_all(db)
+        .await?;
+    let by_status: BTreeMap<String, i64> =
+        rows.iter().map(|r| (r.get("status"), r.get("n"))).collect();
+    Ok(Counts {
+        total: by_status.values().sum(),
+        by_status,
+        computed_at: Some(Utc::now()),
+    })
+}
+
+pub async fn node_count(State(counts): State<Arc<StatusCounts>>) -> Json<serde_json::Value> {
+    let c = counts.get();
+    Json(json!({ "total": c.total, "by_status": c.by_status, "computed_at": c.computed_at }))
+}
+
+/// Prometheus text exposition of the cached counts.
+pub async fn metrics(State(counts): State<Arc<StatusCounts>>) -> impl IntoResponse {
+    let c = counts.get();
+    let mut out = String::from(
+        "# HELP hushnet_nodes Registered nodes by status.\n# TYPE hushnet_nodes gauge\n",
+    );
+    for (status, n) in &c.by_status {
+        out.push_str(&format!("hushnet_nodes{{status=\"{status}\"}} {n}\n"));
+    }
+    out.push_str("# HELP hushnet_nodes_total Registered nodes.\n# TYPE hushnet_nodes_total gauge\n");
+    out.push_str(&format!("hushnet_nodes_total {}\n", c.total));
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+/// Network-wide aggregates for a public status page. Each aggregate is a
+/// separate query; one that fails is reported as `null` with `partial: true`
+/// instead of failing the whole response.
+pub async fn network_stats(
+    State(ReadPool(db)): State<ReadPool>,
+    State(cfg): State<Arc<Config>>,
+) -> Json<serde_json::Value> {
+    let (by_status, by_country, by_protocol_version, avg_latency_ms, uptime_24h_pct) = tokio::join!(
+        stats_by_status(&db),
+        stats_by_country(&db),
+        stats_by_protocol_version(&db),
+        stats_avg_latency(&db, cfg.latency_decimals),
+        stats_uptime_24h(&db),
+    );
+
+    let mut partial = false;
+    let mut part = |name: &str, res: sqlx::Result<serde_json::Value>| {
+        res.unwrap_or_else(|e| {
+            error!("stats: {name} failed: {e}");
+            partial = true;
+            serde_json::Value::Null
+        })
+    };
+    let mut body = json!({
+        "by_status": part("by_status", by_status),
+        "by_country": part("by_country", by_country),
+        "by_protocol_version": part("by_protocol_version", by_protocol_version),
+        "avg_latency_ms": part("avg_latency_ms", avg_latency_ms),
+        "uptime_24h_pct": part("uptime_24h_pct", uptime_24h_pct),
+    });
+    body["partial"] = json!(partial);
+    Json(body)
+}
+
+async fn stats_by_status(db: &PgPool) -> sqlx::Result<serde_json::Value> {
+    let rows = sqlx::query("SELECT status, count(*) AS n FROM nodes WHERE NOT suspended AND approved GROUP BY status")
+        .fetch_all(db)
+        .await?;
+    let m: BTreeMap<String, i64> = rows.iter().map(|r| (r.get("status"), r.get("n"))).collect();
+    Ok(json!(m))
+}
+
+async fn stats_by_country(db: &PgPool) -> sqlx::Result<serde_json::Value> {
+    let rows = sqlx::query(
+        "SELECT country_code, max(country_name) AS country_name, count(*) AS n
+         FROM nodes WHERE NOT suspended AND approved
+         GROUP BY country_code
+         ORDER BY n DESC, country_code",
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            json!({
+                "country_code": r.get::<Option<String>, _>("country_code"),
+                "country_name": r.get::<Option<String>, _>("country_name"),
+                "nodes": r.get::<i64, _>("n"),
+            })
+        })
+        .collect())
+}
+
+async fn stats_by_protocol_version(db: &PgPool) -> sqlx::Result<serde_json::Value> {
+    let rows = sqlx::query(
+        "SELECT protocol_version, count(*) AS n FROM nodes WHERE NOT suspended AND approved GROUP BY protocol_version",
+    )
+    .fetch_all(db)
+    .await?;
+    let m: BTreeMap<String, i64> =
+        rows.iter().map(|r| (r.get("protocol_version"), r.get("n"))).collect();
+    Ok(json!(m))
+}
+
+async fn stats_avg_latency(db: &PgPool, decimals: u32) -> sqlx::Result<serde_json::Value> {
+    let avg: Option<f64> = sqlx::query_scalar(
+        "SELECT avg(last_latency_ms)::float8 FROM nodes WHERE NOT suspended AND approved AND status = 'online'",
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(avg.map_or(serde_json::Value::Null, |ms| round_latency(ms, decimals)))
+}
+
+/// Rounds a latency aggregate to `decimals` places (`LATENCY_DECIMALS`), as an
+/// integer when 0, so insignificant digits never change the response body.
+fn round_latency(ms: f64, decimals: u32) -> serde_json::Value {
+    if decimals == 0 {
+        return json!(ms.round() as i64);
+    }
+    let scale = 10f64.powi(decimals as i32);
+    json!((ms * scale).round() / scale)
+}
+
+async fn stats_uptime_24h(db: &PgPool) -> sqlx::Result<serde_json::Value> {
+    let pct: Option<f64> = sqlx::query_scalar(
+        "SELECT round(100.0 * count(*) FILTER (WHERE status = 'online') / nullif(count(*), 0), 2)::float8
+         FROM health_checks WHERE checked_at >= now() - interval '24 hours'",
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(json!(pct))
+}
@@ -3,10 +3,21 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 #[derive(Deserialize)]
-pub struct ChallengeReq { pub pubkey_b64: String }
+pub struct ChallengeReq {
+    pub pubkey_b64: String,
+    /// Signature algorithm, `ed25519` when absent.
+    pub alg: Option<String>,
+}
 
 #[derive(Serialize)]
-pub struct ChallengeRes { pub nonce: String, pub expires_at: String }
+pub struct ChallengeRes {
+    pub nonce: String,
+    pub expires_at: String,
+    /// Leading zero bits register's `pow_nonce` must reach; absent when
+    /// proof-of-work is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pow_difficulty: Option<u32>,
+}
 
 #[derive(Deserialize)]
 pub struct RegisterReq {
@@ -14,6 +25,10 @@ pub struct RegisterReq {
     pub nonce: String,
     pub signature_b64: String,
     pub pubkey_b64: String,
+    /// Must match the `alg` the challenge was requested with.
+    pub alg: Option<String>,
+    /// Proof-of-work solution for the challenge nonce, when one was asked for.
+    pub pow_nonce: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -22,4 +37,12 @@ pub struct HeartbeatReq {
     pub nonce: String,
     pub signature_b64: String,
     pub pubkey_b64: String,
+    /// Self-reported load, covered by the signature.
+    pub metrics: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct CanonReq {
+    pub payload: Value,
+    pub nonce: Option<String>,
 }
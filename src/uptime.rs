@@ -0,0 +1,101 @@
+// src/uptime.rs
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::error::ApiError;
+use crate::ReadPool;
+
+const MAX_DAYS: i32 = 365;
+
+#[derive(Deserialize)]
+pub struct UptimeQuery {
+    pub days: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub checks: i64,
+    pub online: i64,
+    pub uptime_pct: f64,
+}
+
+#[derive(Serialize)]
+pub struct Uptime {
+    pub host: String,
+    pub days: i32,
+    pub checks: i64,
+    /// `None` when the node has not been probed in the window.
+    pub uptime_pct: Option<f64>,
+    pub daily: Vec<Day>,
+}
+
+fn pct(online: i64, checks: i64) -> f64 {
+    (online as f64 * 10_000.0 / checks as f64).round() / 100.0
+}
+
+/// Uptime over the last `days` days (UTC), from the recorded health probes.
+pub async fn node_uptime(
+    State(ReadPool(db)): State<ReadPool>,
+    Path(host): Path<String>,
+    Query(q): Query<UptimeQuery>,
+) -> Result<Json<Uptime>, ApiError> {
+    let days = q.days.unwrap_or(30);
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("days must be between 1 and {MAX_DAYS}"),
+        ));
+    }
+
+    let exists = sqlx::query("SELECT 1 FROM nodes WHERE host=$1")
+        .bind(&host)
+        .fetch_optional(&db)
+        .await
+        .map_err(crate::internal)?;
+    if exists.is_none() {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "node not found"));
+    }
+
+    let rows = sqlx::query(
+        r#"
+        SELECT (checked_at AT TIME ZONE 'UTC')::date AS day,
+               count(*) AS checks,
+               count(*) FILTER (WHERE status = 'online') AS online
+        FROM health_checks
+        WHERE host = $1 AND checked_at >= now() - make_interval(days => $2)
+        GROUP BY day
+        ORDER BY day
+        "#,
+    )
+    .bind(&host)
+    .bind(days)
+    .fetch_all(&db)
+    .await
+    .map_err(crate::internal)?;
+
+    let daily: Vec<Day> = rows
+        .iter()
+        .map(|r| {
+            let checks: i64 = r.get("checks");
+            let online: i64 = r.get("online");
+            Day { date: r.get("day"), checks, online, uptime_pct: pct(online, checks) }
+        })
+        .collect();
+    let checks: i64 = daily.iter().map(|d| d.checks).sum();
+    let online: i64 = daily.iter().map(|d| d.online).sum();
+
+    Ok(Json(Uptime {
+        host,
+        days,
+        checks,
+        uptime_pct: (checks > 0).then(|| pct(online, checks)),
+        daily,
+    }))
+}
@@ -0,0 +1,599 @@
+// src/validate.rs
+use reqwest::Url;
+use std::net::IpAddr;
+use serde_json::{json, Value};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::FieldError;
+
+/// Register payload fields after validation.
+pub struct NodePayload {
+    pub name: String,
+    pub host: String,
+    pub api_base_url: String,
+    pub protocol_version: String,
+    pub features: Value,
+    pub contact_email: Option<String>,
+    pub max_connections: Option<i32>,
+    pub weight: Option<i32>,
+    pub tags: Vec<String>,
+    /// SHA-256 of the node's leaf TLS certificate, lowercase hex.
+    pub tls_fingerprint: Option<String>,
+    /// Public free-text description, control characters removed.
+    pub description: Option<String>,
+    /// Node software and version, e.g. `hushnetd 1.4.2`.
+    pub software_version: Option<String>,
+    /// Who runs the node: `name`, optional `url` and `pgp_fingerprint`.
+    pub operator: Option<Value>,
+    /// Explicit consent to change the name of an existing node.
+    pub allow_rename: bool,
+}
+
+/// Upper bounds for the capacity hints, to keep obviously bogus values out of
+/// clients' weighted selection.
+const MAX_CONNECTIONS_CAP: i64 = 1_000_000;
+const WEIGHT_CAP: i64 = 10_000;
+
+const MAX_TAGS: usize = 16;
+const MAX_TAG_LEN: usize = 32;
+const MAX_DESCRIPTION_LEN: usize = 280;
+const MAX_SOFTWARE_VERSION_LEN: usize = 64;
+const MAX_OPERATOR_NAME_LEN: usize = 100;
+const MAX_OPERATOR_URL_LEN: usize = 200;
+
+/// Bounds on the free-form `features` value, which is stored as JSONB.
+pub struct FeatureLimits {
+    /// Nesting levels of objects and arrays (`FEATURES_MAX_DEPTH`).
+    pub max_depth: usize,
+    /// Object keys plus array items, counted at every level (`FEATURES_MAX_KEYS`).
+    pub max_keys: usize,
+    /// Length of the serialized JSON (`FEATURES_MAX_BYTES`).
+    pub max_bytes: usize,
+}
+
+impl FeatureLimits {
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: usize| {
+            std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+        };
+        Self {
+            max_depth: var("FEATURES_MAX_DEPTH", 3).max(1),
+            max_keys: var("FEATURES_MAX_KEYS", 64),
+            max_bytes: var("FEATURES_MAX_BYTES", 4096),
+        }
+    }
+
+    /// Whether `value` stays within every limit. The walk stops as soon as
+    /// one is exceeded.
+    fn allows(&self, value: &Value) -> bool {
+        fn walk(v: &Value, depth: usize, limits: &FeatureLimits, keys: &mut usize) -> bool {
+            let children: Box<dyn Iterator<Item = &Value>> = match v {
+                Value::Object(m) => Box::new(m.values()),
+                Value::Array(a) => Box::new(a.iter()),
+                _ => return true,
+            };
+            if depth + 1 > limits.max_depth {
+                return false;
+            }
+            for child in children {
+                *keys += 1;
+                if *keys > limits.max_keys || !walk(child, depth + 1, limits, keys) {
+                    return false;
+                }
+            }
+            true
+        }
+        walk(value, 0, self, &mut 0) && value.to_string().len() <= self.max_bytes
+    }
+}
+
+/// Validates every field of a register payload, reporting all problems
+/// together rather than stopping at the first one. `required` lists optional
+/// fields the deployment wants present anyway.
+pub fn node_payload(
+    payload: &Value,
+    required: &[String],
+    feature_limits: &FeatureLimits,
+) -> Result<NodePayload, Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    for field in required {
+        let present = match payload.get(field) {
+            None | Some(Value::Null) => false,
+            Some(Value::String(s)) => !s.trim().is_empty(),
+            Some(Value::Array(a)) => !a.is_empty(),
+            Some(_) => true,
+        };
+        if !present {
+            errors.push(FieldError::new(field.as_str(), "is required"));
+        }
+    }
+
+    let name = required_str(payload, "name", &mut errors);
+    let host = required_str(payload, "host", &mut errors);
+    let api_base_url = required_str(payload, "api_base_url", &mut errors);
+    let protocol_version = required_str(payload, "protocol_version", &mut errors);
+    let contact_email = optional_str(payload, "contact_email", &mut errors)
+        .map(|e| e.trim().to_owned())
+        .filter(|e| !e.is_empty())
+        .and_then(|e| match normalize_email(&e) {
+            Some(e) => Some(e),
+            None => {
+                errors.push(FieldError::new("contact_email", "is not a valid email address"));
+                None
+            }
+        });
+    let max_connections = optional_int(payload, "max_connections", MAX_CONNECTIONS_CAP, &mut errors);
+    let weight = optional_int(payload, "weight", WEIGHT_CAP, &mut errors);
+    let tags = tags(payload, &mut errors);
+    let tls_fingerprint = optional_str(payload, "tls_fingerprint", &mut errors).and_then(|raw| {
+        let hex: String = raw.trim().chars().filter(|c| *c != ':').collect::<String>().to_ascii_lowercase();
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            errors.push(FieldError::new("tls_fingerprint", "must be a hex SHA-256 digest"));
+            return None;
+        }
+        let https = api_base_url.as_deref().and_then(|u| Url::parse(u).ok()).is_some_and(|u| u.scheme() == "https");
+        if !https {
+            errors.push(FieldError::new("tls_fingerprint", "requires an https api_base_url"));
+            return None;
+        }
+        Some(hex)
+    });
+    let description = optional_str(payload, "description", &mut errors).and_then(|raw| {
+        // Line breaks and tabs become spaces; other control characters
+        // (including bidi overrides) are dropped so UIs render it as one line.
+        let cleaned: String = raw
+            .chars()
+            .filter_map(|c| match c {
+                '\n' | '\r' | '\t' => Some(' '),
+                c if c.is_control() || matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}') => None,
+                c => Some(c),
+            })
+            .collect();
+        let cleaned = cleaned.trim();
+        if cleaned.chars().count() > MAX_DESCRIPTION_LEN {
+            errors.push(FieldError::new(
+                "description",
+                format!("must be at most {MAX_DESCRIPTION_LEN} characters"),
+            ));
+            return None;
+        }
+        (!cleaned.is_empty()).then(|| cleaned.to_owned())
+    });
+    let software_version = optional_str(payload, "software_version", &mut errors)
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .and_then(|v| {
+            if v.chars().count() > MAX_SOFTWARE_VERSION_LEN || v.chars().any(char::is_control) {
+                errors.push(FieldError::new(
+                    "software_version",
+                    format!("must be at most {MAX_SOFTWARE_VERSION_LEN} printable characters"),
+                ));
+                return None;
+            }
+            Some(v)
+        });
+    let operator = match payload.get("operator") {
+        None | Some(Value::Null) => None,
+        Some(v) => operator(v, &mut errors),
+    };
+    let allow_rename = match payload.get("allow_rename") {
+        None | Some(Value::Null) => false,
+        Some(Value::Bool(b)) => *b,
+        Some(_) => {
+            errors.push(FieldError::new("allow_rename", "must be a boolean"));
+            false
+        }
+    };
+
+    let features = payload.get("features").cloned().unwrap_or(json!({}));
+    if !feature_limits.allows(&features) {
+        errors.push(FieldError::new(
+            "features",
+            format!(
+                "too complex (max depth {}, {} keys, {} bytes)",
+                feature_limits.max_depth, feature_limits.max_keys, feature_limits.max_bytes
+            ),
+        ));
+    }
+
+    if let Some(api) = &api_base_url {
+        match Url::parse(api) {
+            Ok(u) if matches!(u.scheme(), "http" | "https") && u.host().is_some() => {}
+            _ => errors.push(FieldError::new("api_base_url", "must be an http(s) URL")),
+        }
+    }
+
+    if let Some(host) = host.as_deref().filter(|h| crate::dns::is_onion(h)) {
+        // v3 addresses: 56 base32 characters before `.onion`.
+        let label = &host[..host.len() - ".onion".len()];
+        let label = label.rsplit('.').next().unwrap_or(label);
+        if label.len() != 56 || !label.bytes().all(|b| matches!(b, b'a'..=b'z' | b'2'..=b'7')) {
+            errors.push(FieldError::new("host", "is not a valid v3 onion address"));
+        }
+    }
+
+    match (name, host, api_base_url, protocol_version) {
+        (Some(name), Some(host), Some(api_base_url), Some(protocol_version)) if errors.is_empty() => {
+            Ok(NodePayload {
+                name,
+                host,
+                api_base_url,
+                protocol_version,
+                features,
+                contact_email,
+                max_connections,
+                weight,
+                tags,
+                tls_fingerprint,
+                description,
+                software_version,
+                operator,
+                allow_rename,
+            })
+        }
+        _ => Err(errors),
+    }
+}
+
+/// Checks the `operator` object and returns it normalized: `name` trimmed,
+/// `url` an http(s) URL, `pgp_fingerprint` as uppercase hex without spaces
+/// (40 digits for v4 keys, 64 for v5). Absent optional keys are left out.
+fn operator(value: &Value, errors: &mut Vec<FieldError>) -> Option<Value> {
+    let Some(obj) = value.as_object() else {
+        errors.push(FieldError::new("operator", "must be an object"));
+        return None;
+    };
+    let before = errors.len();
+    for key in obj.keys().filter(|k| !matches!(k.as_str(), "name" | "url" | "pgp_fingerprint")) {
+        errors.push(FieldError::new(format!("operator.{key}"), "unknown field"));
+    }
+    let text = |key: &str, errors: &mut Vec<FieldError>| match obj.get(key) {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) => Some(s.trim().to_owned()).filter(|s| !s.is_empty()),
+        Some(_) => {
+            errors.push(FieldError::new(format!("operator.{key}"), "must be a string"));
+            None
+        }
+    };
+
+    let mut out = serde_json::Map::new();
+    match text("name", errors) {
+        None => errors.push(FieldError::new("operator.name", "is required")),
+        Some(name) if name.chars().count() > MAX_OPERATOR_NAME_LEN || name.chars().any(char::is_control) => {
+            errors.push(FieldError::new(
+                "operator.name",
+                format!("must be at most {MAX_OPERATOR_NAME_LEN} printable characters"),
+            ));
+        }
+        Some(name) => {
+            out.insert("name".into(), json!(name));
+        }
+    }
+    if let Some(url) = text("url", errors) {
+        let valid = url.len() <= MAX_OPERATOR_URL_LEN
+            && Url::parse(&url).is_ok_and(|u| matches!(u.scheme(), "http" | "https") && u.host().is_some());
+        if valid {
+            out.insert("url".into(), json!(url));
+        } else {
+            errors.push(FieldError::new(
+                "operator.url",
+                format!("must be an http(s) URL of at most {MAX_OPERATOR_URL_LEN} characters"),
+            ));
+        }
+    }
+    if let Some(raw) = text("pgp_fingerprint", errors) {
+        let hex: String = raw.chars().filter(|c| *c != ' ').collect::<String>().to_ascii_uppercase();
+        if matches!(hex.len(), 40 | 64) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            out.insert("pgp_fingerprint".into(), json!(hex));
+        } else {
+            errors.push(FieldError::new("operator.pgp_fingerprint", "must be a 40 or 64 digit hex fingerprint"));
+        }
+    }
+    (errors.len() == before).then_some(Value::Object(out))
+}
+
+/// Whether `api_base_url` points at `host` itself. Both go through the URL
+/// host parser, so domains are compared after IDNA and case normalization
+/// and IP addresses in canonical form; a trailing dot is ignored.
+pub fn api_host_matches(host: &str, api_base_url: &str) -> bool {
+    let url_host = |url: &str| {
+        Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.trim_end_matches('.').to_owned()))
+    };
+    let host = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{ip}]"),
+        _ => host.to_owned(),
+    };
+    match (url_host(&format!("http://{host}/")), url_host(api_base_url)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Folds a node name for reserved-name matching: NFKC (so full-width and
+/// other compatibility forms collapse to plain letters), lowercased, with
+/// everything but letters and digits dropped. `HushNet Official`,
+/// `hushnet-official` and `ＨｕｓｈＮｅｔＯｆｆｉｃｉａｌ` all fold to `hushnetofficial`.
+pub fn name_key(name: &str) -> String {
+    name.nfkc().flat_map(char::to_lowercase).filter(|c| c.is_alphanumeric()).collect()
+}
+
+fn required_str(payload: &Value, field: &str, errors: &mut Vec<FieldError>) -> Option<String> {
+    match payload.get(field) {
+        None | Some(Value::Null) => {
+            errors.push(FieldError::new(field, "is required"));
+            None
+        }
+        Some(Value::String(s)) if s.trim().is_empty() => {
+            errors.push(FieldError::new(field, "must not be empty"));
+            None
+        }
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(_) => {
+            errors.push(FieldError::new(field, "must be a string"));
+            None
+        }
+    }
+}
+
+fn optional_str(payload: &Value, field: &str, errors: &mut Vec<FieldError>) -> Option<String> {
+    match payload.get(field) {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(_) => {
+            errors.push(FieldError::new(field, "must be a string"));
+            None
+        }
+    }
+}
+
+fn optional_int(payload: &Value, field: &str, max: i64, errors: &mut Vec<FieldError>) -> Option<i32> {
+    match payload.get(field) {
+        None | Some(Value::Null) => None,
+        Some(v) => match v.as_i64() {
+            Some(n) if (0..=max).contains(&n) => Some(n as i32),
+            Some(_) => {
+                errors.push(FieldError::new(field, format!("must be between 0 and {max}")));
+                None
+            }
+            None => {
+                errors.push(FieldError::new(field, "must be an integer"));
+                None
+            }
+        },
+    }
+}
+
+/// Keys a heartbeat's `metrics` object may carry.
+const METRICS: &[&str] = &["cpu", "connections", "uptime_secs"];
+
+/// Checks a heartbeat's self-reported `metrics`: an object with any of
+/// `cpu` (percent, 0–100), `connections` and `uptime_secs` (non-negative
+/// integers). Returns it with only those keys, nulls dropped.
+pub fn heartbeat_metrics(metrics: &Value) -> Result<Value, Vec<FieldError>> {
+    let Some(obj) = metrics.as_object() else {
+        return Err(vec![FieldError::new("metrics", "must be an object")]);
+    };
+    let mut errors = Vec::new();
+    for key in obj.keys().filter(|k| !METRICS.contains(&k.as_str())) {
+        errors.push(FieldError::new(format!("metrics.{key}"), "unknown metric"));
+    }
+    let mut out = serde_json::Map::new();
+    match obj.get("cpu") {
+        None | Some(Value::Null) => {}
+        Some(v) => match v.as_f64() {
+            Some(cpu) if (0.0..=100.0).contains(&cpu) => {
+                out.insert("cpu".into(), v.clone());
+            }
+            _ => errors.push(FieldError::new("metrics.cpu", "must be a number between 0 and 100")),
+        },
+    }
+    for key in ["connections", "uptime_secs"] {
+        match obj.get(key) {
+            None | Some(Value::Null) => {}
+            Some(v) => match v.as_u64() {
+                Some(n) if n <= i64::MAX as u64 => {
+                    out.insert(key.into(), json!(n));
+                }
+                _ => errors.push(FieldError::new(format!("metrics.{key}"), "must be a non-negative integer")),
+            },
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Value::Object(out))
+}
+
+/// Whether the signed `payload` names `pubkey` in its `pubkey` field, compared
+/// as decoded bytes so padding and whitespace do not matter.
+pub fn pubkey_bound(payload: &Value, pubkey: &[u8]) -> bool {
+    let bound = payload
+        .get("pubkey")
+        .and_then(|v| v.as_str())
+        .filter(|s| s.len() <= crate::crypto::MAX_PUBKEY_B64_LEN)
+        .map(crate::crypto::decode_b64);
+    matches!(bound, Some(Ok(k)) if k == pubkey)
+}
+
+/// Checks the overall shape of an address (`local@domain.tld`) and lowercases
+/// the domain. The local part is kept as-is since it may be case-sensitive.
+fn normalize_email(email: &str) -> Option<String> {
+    let (local, domain) = email.rsplit_once('@')?;
+    let local_ok = !local.is_empty()
+        && local.len() <= 64
+        && !local.contains('@')
+        && !local.chars().any(|c| c.is_whitespace() || c.is_control());
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_ok = email.len() <= 254
+        && labels.len() >= 2
+        && labels.iter().all(|l| {
+            !l.is_empty()
+                && l.len() <= 63
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    (local_ok && domain_ok).then(|| format!("{local}@{}", domain.to_ascii_lowercase()))
+}
+
+/// Optional list of short labels; trimmed and deduplicated, order kept.
+pub fn tags(payload: &Value, errors: &mut Vec<FieldError>) -> Vec<String> {
+    let items = match payload.get("tags") {
+        None | Some(Value::Null) => return Vec::new(),
+        Some(Value::Array(items)) => items,
+        Some(_) => {
+            errors.push(FieldError::new("tags", "must be an array of strings"));
+            return Vec::new();
+        }
+    };
+    let mut tags: Vec<String> = Vec::new();
+    for item in items {
+        match item.as_str().map(str::trim) {
+            Some(t) if !t.is_empty() && t.chars().count() <= MAX_TAG_LEN => {
+                if !tags.iter().any(|x| x == t) {
+                    tags.push(t.to_owned());
+                }
+            }
+            Some(_) => {
+                errors.push(FieldError::new("tags", format!("each tag must be 1 to {MAX_TAG_LEN} characters")));
+                return Vec::new();
+            }
+            None => {
+                errors.push(FieldError::new("tags", "must be an array of strings"));
+                return Vec::new();
+            }
+        }
+    }
+    if tags.len() > MAX_TAGS {
+        errors.push(FieldError::new("tags", format!("at most {MAX_TAGS} tags")));
+        return Vec::new();
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> FeatureLimits {
+        FeatureLimits { max_depth: 3, max_keys: 64, max_bytes: 4096 }
+    }
+
+    fn payload(extra: Value) -> Value {
+        let mut payload = json!({
+            "name": "node",
+            "host": "node.example.com",
+            "api_base_url": "https://node.example.com",
+            "protocol_version": "1.0",
+        });
+        payload.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        payload
+    }
+
+    #[test]
+    fn valid_emails_are_accepted_with_the_domain_lowercased() {
+        for (input, normalized) in [
+            ("ops@example.com", "ops@example.com"),
+            ("Ops.Team+hush@Mail.Example.COM", "Ops.Team+hush@mail.example.com"),
+            ("a@b-c.io", "a@b-c.io"),
+        ] {
+            assert_eq!(normalize_email(input).as_deref(), Some(normalized), "{input}");
+        }
+    }
+
+    #[test]
+    fn invalid_emails_are_rejected() {
+        for input in [
+            "",
+            "ops",
+            "@example.com",
+            "ops@",
+            "ops@localhost",
+            "ops@example..com",
+            "ops@-example.com",
+            "ops@example.com-",
+            "ops@exa_mple.com",
+            "o ps@example.com",
+            "a@b@example.com",
+        ] {
+            assert_eq!(normalize_email(input), None, "{input}");
+        }
+        assert_eq!(normalize_email(&format!("{}@example.com", "a".repeat(65))), None);
+    }
+
+    #[test]
+    fn contact_email_is_trimmed_and_optional() {
+        let node = node_payload(&payload(json!({ "contact_email": "  ops@EXAMPLE.com \n" })), &[], &limits()).unwrap();
+        assert_eq!(node.contact_email.as_deref(), Some("ops@example.com"));
+        let node = node_payload(&payload(json!({ "contact_email": "  " })), &[], &limits()).unwrap();
+        assert_eq!(node.contact_email, None);
+        let node = node_payload(&payload(json!({})), &[], &limits()).unwrap();
+        assert_eq!(node.contact_email, None);
+    }
+
+    #[test]
+    fn invalid_contact_email_is_a_field_error() {
+        let errors = node_payload(&payload(json!({ "contact_email": "not an email" })), &[], &limits())
+            .err()
+            .unwrap();
+        assert!(errors.iter().any(|e| e.field == "contact_email"));
+    }
+
+    #[test]
+    fn payload_must_name_the_signing_key() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        let key = [7u8; 32];
+        let b64 = STANDARD.encode(key);
+        assert!(pubkey_bound(&json!({ "pubkey": b64 }), &key));
+        assert!(pubkey_bound(&json!({ "pubkey": b64.trim_end_matches('=') }), &key));
+
+        assert!(!pubkey_bound(&json!({}), &key));
+        assert!(!pubkey_bound(&json!({ "pubkey": null }), &key));
+        assert!(!pubkey_bound(&json!({ "pubkey": STANDARD.encode([8u8; 32]) }), &key));
+        assert!(!pubkey_bound(&json!({ "pubkey": "not base64!" }), &key));
+        assert!(!pubkey_bound(&json!({ "pubkey": format!("{b64}{}", " ".repeat(64)) }), &key));
+    }
+
+    /// `levels` objects nested inside each other, innermost holding a scalar.
+    fn nested(levels: usize) -> Value {
+        (0..levels).fold(json!(1), |inner, _| json!({ "k": inner }))
+    }
+
+    #[test]
+    fn features_at_the_depth_limit_are_accepted() {
+        let limits = limits();
+        assert!(limits.allows(&nested(limits.max_depth)));
+        let node = node_payload(&payload(json!({ "features": nested(3) })), &[], &limits).unwrap();
+        assert_eq!(node.features, nested(3));
+        // Arrays count as levels too.
+        assert!(limits.allows(&json!({ "a": [[1]] })));
+    }
+
+    #[test]
+    fn features_just_over_the_depth_limit_are_rejected() {
+        let limits = limits();
+        assert!(!limits.allows(&nested(limits.max_depth + 1)));
+        assert!(!limits.allows(&json!({ "a": [[{}]] })));
+        let errors = node_payload(&payload(json!({ "features": nested(4) })), &[], &limits).err().unwrap();
+        assert!(errors.iter().any(|e| e.field == "features" && e.message.starts_with("too complex")));
+    }
+
+    #[test]
+    fn deeply_nested_features_are_rejected_without_recursing_through_them() {
+        let limits = limits();
+        // Far deeper than the limit; the walk gives up at the limit.
+        let deep = (0..1_000).fold(json!(1), |inner, _| Value::Array(vec![inner]));
+        assert!(!limits.allows(&deep));
+    }
+
+    #[test]
+    fn features_over_the_key_or_size_limit_are_rejected() {
+        let limits = limits();
+        let many: serde_json::Map<String, Value> = (0..=limits.max_keys).map(|i| (i.to_string(), json!(i))).collect();
+        assert!(!limits.allows(&Value::Object(many)));
+        assert!(!limits.allows(&json!({ "k": "x".repeat(limits.max_bytes) })));
+    }
+}
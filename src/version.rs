@@ -0,0 +1,51 @@
+// src/version.rs
+use std::cmp::Ordering;
+
+/// Parses a dotted numeric protocol version such as `1.2` or `v2.0.1`.
+/// Missing trailing components compare as zero, so `1` == `1.0`.
+pub fn parse(v: &str) -> Option<Vec<u64>> {
+    let v = v.trim();
+    let v = v.strip_prefix(['v', 'V']).unwrap_or(v);
+    if v.is_empty() {
+        return None;
+    }
+    v.split('.').map(|p| p.parse().ok()).collect()
+}
+
+pub fn compare(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// How a client's protocol version is matched against node versions when
+/// filtering the node list.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolCompat {
+    /// Node version must equal the requested one.
+    Exact,
+    /// Node version must be greater than or equal to the requested one.
+    AtLeast,
+}
+
+impl ProtocolCompat {
+    pub fn from_env() -> Self {
+        match std::env::var("PROTOCOL_COMPAT").as_deref() {
+            Ok("gte") => Self::AtLeast,
+            _ => Self::Exact,
+        }
+    }
+
+    pub fn matches(self, node: &str, wanted: &str) -> bool {
+        match (parse(node), parse(wanted)) {
+            (Some(n), Some(w)) => match self {
+                Self::Exact => compare(&n, &w).is_eq(),
+                Self::AtLeast => compare(&n, &w).is_ge(),
+            },
+            // Unparseable versions only ever match themselves verbatim.
+            _ => node == wanted,
+        }
+    }
+}